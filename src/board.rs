@@ -1,97 +1,519 @@
-use crate::nnue::Network;
+mod zobrist;
+
+use crate::nnue::{refreshed_accumulators, Network};
+use crate::search::see_square;
 use crate::types::Score;
-use crate::types::MAX_PLY;
+use crate::types::{MAX_PLY, SEE_VALUES};
 use shakmaty::{
+    attacks,
     fen::{Fen, ParseFenError},
+    san::SanPlus,
     uci::UciMove,
+    variant::VariantPosition,
     zobrist::{Zobrist64, ZobristHash},
-    CastlingMode, Chess, Color, EnPassantMode, Move, MoveList, Position, Role, Square,
+    Bitboard, CastlingMode, Chess, Color, EnPassantMode, File, Move, MoveList, Piece, Position,
+    PositionError, Role, Square,
 };
+use std::cell::Cell;
+use std::fmt;
+
+/// Rules the board is played under, selected with the `UCI_Variant` option.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Variant {
+    #[default]
+    Standard,
+    Crazyhouse,
+}
+
+impl Variant {
+    pub fn from_uci(name: &str) -> Option<Self> {
+        match name {
+            "chess" => Some(Self::Standard),
+            "crazyhouse" => Some(Self::Crazyhouse),
+            _ => None,
+        }
+    }
+}
+
+impl From<Variant> for shakmaty::variant::Variant {
+    fn from(variant: Variant) -> Self {
+        match variant {
+            Variant::Standard => Self::Chess,
+            Variant::Crazyhouse => Self::Crazyhouse,
+        }
+    }
+}
+
+/// A move in UCI notation that couldn't be played.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MoveError {
+    /// Not a move in UCI notation at all.
+    Invalid(String),
+    /// Well formed, but not legal in the position.
+    Illegal(String),
+}
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Invalid(uci) => write!(f, "invalid move {uci}"),
+            Self::Illegal(uci) => write!(f, "illegal move {uci}"),
+        }
+    }
+}
+
+impl std::error::Error for MoveError {}
+
+/// A FEN that couldn't be set up.
+#[derive(Debug)]
+pub enum FenError {
+    /// Not a FEN at all.
+    Invalid(ParseFenError),
+    /// Well formed, but not a legal position, like one without a king.
+    Illegal(Box<PositionError<VariantPosition>>),
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Invalid(err) => err.fmt(f),
+            Self::Illegal(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for FenError {}
+
+/// Number of recent plies checked for repetitions before trusting a TT cutoff.
+const REPETITION_WINDOW: usize = 16;
+
+/// Denominator of the endgame scale factor, the share of the evaluation that is kept.
+const SCALE_NORMAL: i32 = 64;
+
+/// Mating bonus per step the lone king is pushed towards the edge, and per step the kings close in.
+const MATE_EDGE_BONUS: i32 = 20;
+const MATE_KING_BONUS: i32 = 10;
 
 #[derive(Clone)]
 pub struct Board {
-    pos: Chess,
+    pos: VariantPosition,
+    variant: Variant,
     nnue: Network,
-    state_stack: Vec<Chess>,
+    state_stack: Vec<VariantPosition>,
+    hash: u64,
+    hash_stack: Vec<u64>,
     move_stack: Vec<Option<Move>>,
     history: Vec<u64>,
     ply: usize,
     eval_stack: [i32; MAX_PLY],
+    /// Whether the side to move is in check, once asked. Cleared whenever the position changes.
+    in_check: Cell<Option<bool>>,
 }
 
 impl Board {
-    pub fn new(fen: &str) -> Result<Self, ParseFenError> {
-        let fen_string = String::from(fen);
-        let fen: Fen = fen_string.parse()?;
-        let pos: Chess = fen.into_position(CastlingMode::Standard).unwrap();
-        let mut nnue = Network::default();
-        for color in [Color::White, Color::Black] {
-            for piece in Role::ALL {
-                for square in pos.board().by_color(color) & pos.board().by_role(piece) {
-                    nnue.accumulate(color, piece, square);
-                }
-            }
-        }
+    /// Parses a FEN. Trailing fields may be left out, and default to no castling rights,
+    /// no en passant square and move counters of `0 1`.
+    pub fn new(fen: &str) -> Result<Self, FenError> {
+        Self::with_variant(fen, Variant::Standard)
+    }
+
+    pub fn with_variant(fen: &str, variant: Variant) -> Result<Self, FenError> {
+        let pos = parse_position(fen, variant)?;
+        let nnue = refreshed_network(pos.board(), None);
         let state_stack = Vec::default();
         let move_stack = Vec::default();
         let history = Vec::default();
-        Ok(Self {
+        let mut board = Self {
             pos,
+            variant,
             nnue,
             state_stack,
+            hash: 0,
+            hash_stack: Vec::default(),
             move_stack,
             history,
             ply: 0,
             eval_stack: [0; MAX_PLY],
-        })
+            in_check: Cell::new(None),
+        };
+        board.hash = board.full_hash();
+        board.history.push(board.hash);
+        Ok(board)
+    }
+
+    /// Sets up a new position under the same variant, like `with_variant` would, but reuses
+    /// the stacks and the network instead of allocating new ones. The board is left as it was
+    /// if the FEN doesn't parse or isn't a legal position.
+    pub fn set_from_fen(&mut self, fen: &str) -> Result<(), FenError> {
+        self.pos = parse_position(fen, self.variant)?;
+        self.in_check.set(None);
+        self.nnue.reset();
+        refresh(&mut self.nnue, self.pos.board(), None);
+        self.state_stack.clear();
+        self.hash_stack.clear();
+        self.move_stack.clear();
+        self.history.clear();
+        self.ply = 0;
+        self.eval_stack = [0; MAX_PLY];
+        self.hash = self.full_hash();
+        self.history.push(self.hash);
+        Ok(())
+    }
+
+    pub fn variant(&self) -> Variant {
+        self.variant
     }
 
     pub fn starting_position() -> Self {
         Self::new("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap()
     }
 
-    pub fn has_non_pawn_material(&self) -> bool {
-        let our_pieces = self.pos.us();
-        (self.pos.board().by_role(Role::Pawn) | self.pos.board().by_role(Role::King)) & our_pieces
-            != our_pieces
+    /// Number of pieces on the board, kings and pawns included.
+    pub fn piece_count(&self) -> u32 {
+        self.pos.board().occupied().count() as u32
     }
-    pub fn play_uci(&mut self, uci_move: &str) {
-        let moves = self.legal_moves();
-        for mv in moves {
-            if self.to_uci(&mv).to_string() == uci_move {
-                self.make_move::<true>(&mv);
-                break;
+
+    /// Piece counts of both sides packed four bits per color and role, so positions with the
+    /// same material share a key. No count can exceed 15 in a legal position.
+    pub fn material_key(&self) -> u64 {
+        let board = self.pos.board();
+        let mut key = 0;
+        for color in Color::ALL {
+            for role in Role::ALL {
+                let count = (board.by_role(role) & board.by_color(color)).count() as u64;
+                key |= count << (4 * (6 * color as usize + role as usize - 1));
             }
         }
+        key
+    }
+
+    /// Value of the knights, bishops, rooks and queens of a side.
+    pub fn non_pawn_material(&self, color: Color) -> i32 {
+        let board = self.pos.board();
+        [Role::Knight, Role::Bishop, Role::Rook, Role::Queen]
+            .into_iter()
+            .map(|role| {
+                (board.by_role(role) & board.by_color(color)).count() as i32
+                    * SEE_VALUES[role as usize]
+            })
+            .sum()
     }
+
+    /// Each side has a lone bishop and the two stand on different colored squares.
+    fn is_opposite_bishops(&self) -> bool {
+        // A knight is worth as much as a bishop, so the material alone doesn't tell them apart
+        let board = self.board();
+        let lone_bishop = |color| {
+            self.non_pawn_material(color) == SEE_VALUES[Role::Bishop as usize]
+                && (board.bishops() & board.by_color(color)).count() == 1
+        };
+        lone_bishop(Color::White)
+            && lone_bishop(Color::Black)
+            && (board.bishops() & Bitboard::LIGHT_SQUARES).count() == 1
+    }
+
+    /// How much of the evaluation to keep, out of `SCALE_NORMAL`, in endings that the side the
+    /// evaluation favours (`strong`) can rarely win. Crazyhouse is never scaled, since captured
+    /// pieces come back into play.
+    fn scale_factor(&self, strong: Color) -> i32 {
+        if self.variant != Variant::Standard {
+            return SCALE_NORMAL;
+        }
+        let board = self.pos.board();
+        let pawns = |color| (board.pawns() & board.by_color(color)).count() as i32;
+
+        // Without pawns, an edge of a minor piece or less can't force mate, as in KRvKR or KRvKB
+        let edge = self.non_pawn_material(strong) - self.non_pawn_material(!strong);
+        if pawns(strong) == 0 && edge <= SEE_VALUES[Role::Bishop as usize] {
+            return 4;
+        }
+        // Opposite colored bishop endings are drawish, and a pawn or two up rarely changes that
+        if self.is_opposite_bishops() {
+            return if pawns(strong) - pawns(!strong) <= 2 {
+                16
+            } else {
+                32
+            };
+        }
+        SCALE_NORMAL
+    }
+
+    /// Bonus for the side to move in mates against a lone king, which the network plays
+    /// without a plan: drive that king to the edge, or to a corner of the bishop's color in
+    /// KBNK, and bring our king closer.
+    fn mating_bonus(&self) -> i32 {
+        if self.variant != Variant::Standard {
+            return 0;
+        }
+        let board = self.pos.board();
+        let Some(strong) = Color::ALL.into_iter().find(|&color| {
+            board.by_color(!color).count() == 1 && board.by_color(color).count() > 1
+        }) else {
+            return 0;
+        };
+        let (Some(king), Some(lone)) = (board.king_of(strong), board.king_of(!strong)) else {
+            return 0;
+        };
+
+        let ours = board.by_color(strong);
+        let majors = (board.queens() | board.rooks()) & ours;
+        let bishops = board.bishops() & ours;
+        let knights = board.knights() & ours;
+        let can_mate = majors.any()
+            || (bishops.any() && knights.any())
+            || ((bishops & Bitboard::LIGHT_SQUARES).any()
+                && (bishops & Bitboard::DARK_SQUARES).any());
+        if !can_mate {
+            return 0;
+        }
+
+        let edge = if majors.is_empty() && bishops.count() == 1 && knights.any() {
+            let corners = if (bishops & Bitboard::LIGHT_SQUARES).any() {
+                [Square::A8, Square::H1]
+            } else {
+                [Square::A1, Square::H8]
+            };
+            7 - corners
+                .map(|corner| lone.distance(corner))
+                .into_iter()
+                .min()
+                .unwrap() as i32
+        } else {
+            let (file, rank) = (lone.file() as i32, lone.rank() as i32);
+            6 - file.min(7 - file) - rank.min(7 - rank)
+        };
+        let bonus = MATE_EDGE_BONUS * edge + MATE_KING_BONUS * (7 - king.distance(lone) as i32);
+        if self.pos.turn() == strong {
+            bonus
+        } else {
+            -bonus
+        }
+    }
+
+    /// Plays a move in UCI notation, leaving the board untouched if it isn't legal. Castling is
+    /// accepted both as the king's two-square move (`e1g1`) and as king takes rook (`e1h1`), as
+    /// Chess960 GUIs send it.
+    pub fn try_play_uci(&mut self, uci_move: &str) -> Result<(), MoveError> {
+        let uci: UciMove = uci_move
+            .parse()
+            .map_err(|_| MoveError::Invalid(uci_move.to_string()))?;
+        let mv = uci
+            .to_move(&self.pos)
+            .map_err(|_| MoveError::Illegal(uci_move.to_string()))?;
+        self.make_move::<true>(&mv);
+        Ok(())
+    }
+
+    /// Like `try_play_uci`, for tests whose moves are known to be legal.
+    #[cfg(test)]
+    pub fn play_uci(&mut self, uci_move: &str) {
+        self.try_play_uci(uci_move).expect("illegal move");
+    }
+
+    /// Move that led to the current position. `None` at the root of the game and after a null
+    /// move.
+    pub fn last_move(&self) -> Option<Move> {
+        self.tail_move(1)
+    }
+
+    /// Move played before `last_move`, with the same conventions.
+    pub fn move_before_last(&self) -> Option<Move> {
+        self.tail_move(2)
+    }
+
+    /// Move played `index` plies ago, counting from 1: `tail_move(1)` is `last_move()` and
+    /// `tail_move(2)` is `move_before_last()`. Prefer those for the first two plies.
     pub fn tail_move(&self, index: usize) -> Option<Move> {
         match self.move_stack.len().checked_sub(index) {
             Some(index) => self.move_stack[index].clone(),
             None => None,
         }
     }
+    /// Returns the hash of the current position, maintained incrementally by `make_move`.
     pub fn get_hash(&self) -> u64 {
-        self.pos.zobrist_hash::<Zobrist64>(EnPassantMode::Legal).0
+        self.hash
+    }
+
+    /// Returns the key a node searched without `excluded` is stored under, so that such searches
+    /// don't share TT entries with normal ones. The move key is xored in, so xoring it again
+    /// restores the position hash.
+    pub fn hash_excluding(&self, excluded: &Option<Move>) -> u64 {
+        match excluded {
+            Some(mv) => self.hash ^ zobrist::excluded(mv),
+            None => self.hash,
+        }
+    }
+
+    /// Returns the hash `make_move` would leave behind, without making the move, so the TT
+    /// entry of the child can be prefetched early.
+    pub fn hash_after(&self, mv: &Move) -> u64 {
+        // Double pushes need the legality of the en passant capture they allow
+        let double_push =
+            mv.role() == Role::Pawn && mv.from().is_some_and(|from| from.distance(mv.to()) == 2);
+        if self.variant == Variant::Crazyhouse || double_push {
+            let mut pos = self.pos.clone();
+            pos.play_unchecked(mv);
+            return position_hash(&pos, self.variant);
+        }
+
+        let us = self.pos.turn();
+        let mut hash = self.hash ^ zobrist::state(&self.pos) ^ zobrist::state_after(&self.pos, mv);
+        match mv {
+            Move::Normal {
+                role,
+                from,
+                capture,
+                to,
+                promotion,
+            } => {
+                hash ^= zobrist::piece(us, *role, *from);
+                hash ^= zobrist::piece(us, promotion.unwrap_or(*role), *to);
+                if let Some(capture) = capture {
+                    hash ^= zobrist::piece(!us, *capture, *to);
+                }
+            }
+            Move::EnPassant { from, to } => {
+                hash ^= zobrist::piece(us, Role::Pawn, *from);
+                hash ^= zobrist::piece(us, Role::Pawn, *to);
+                hash ^= zobrist::piece(!us, Role::Pawn, to.xor(Square::A2));
+            }
+            Move::Castle { king, rook } => {
+                let (king_to, rook_to) = if rook > king {
+                    (File::G, File::F)
+                } else {
+                    (File::C, File::D)
+                };
+                hash ^= zobrist::piece(us, Role::King, *king);
+                hash ^= zobrist::piece(us, Role::Rook, *rook);
+                hash ^= zobrist::piece(us, Role::King, Square::from_coords(king_to, king.rank()));
+                hash ^= zobrist::piece(us, Role::Rook, Square::from_coords(rook_to, rook.rank()));
+            }
+            Move::Put { role, to } => hash ^= zobrist::piece(us, *role, *to),
+        }
+        hash
+    }
+
+    fn full_hash(&self) -> u64 {
+        position_hash(&self.pos, self.variant)
+    }
+
+    pub fn fen(&self) -> String {
+        Fen::from_position(self.pos.clone(), EnPassantMode::Legal).to_string()
+    }
+
+    /// Returns the same position with colors swapped and ranks flipped. A symmetric evaluation
+    /// scores it the same from the side to move's perspective.
+    pub fn mirror(&self) -> Self {
+        Self::with_variant(&mirror_fen(&self.fen()), self.variant).unwrap()
     }
 
     pub fn to_uci(&self, mv: &Move) -> UciMove {
         mv.to_uci(self.pos.castles().mode())
     }
 
+    /// A line of legal moves in standard algebraic notation, with check and mate suffixes.
+    /// Each move is converted in the position it's played from, so that disambiguation and
+    /// suffixes come out right. A single move is just a line of one.
+    pub fn line_to_san(&self, line: &[Move]) -> String {
+        let mut pos = self.pos.clone();
+        line.iter()
+            .map(|mv| SanPlus::from_move_and_play_unchecked(&mut pos, mv).to_string())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
     pub fn turn(&self) -> Color {
         self.pos.turn()
     }
 
+    /// Computed once per position, since a node asks several times.
     pub fn in_check(&self) -> bool {
-        self.pos.is_check()
+        let in_check = self.in_check.get().unwrap_or_else(|| {
+            let in_check = self.pos.is_check();
+            self.in_check.set(Some(in_check));
+            in_check
+        });
+        debug_assert_eq!(in_check, self.pos.is_check(), "{}", self.fen());
+        in_check
+    }
+
+    /// Returns whether the move checks the opponent king, including discovered checks,
+    /// without playing it.
+    pub fn gives_check(&self, mv: &Move) -> bool {
+        let board = self.pos.board();
+        let us = self.pos.turn();
+        let Some(king) = board.king_of(!us) else {
+            return false;
+        };
+
+        match mv {
+            Move::Normal {
+                role,
+                from,
+                to,
+                promotion,
+                ..
+            } => {
+                let occupied = board.occupied().without(*from).with(*to);
+                let piece = promotion.unwrap_or(*role).of(us);
+                attacks::attacks(*to, piece, occupied).contains(king)
+                    || board.attacks_to(king, us, occupied).without(*from).any()
+            }
+            Move::Put { role, to } => {
+                let occupied = board.occupied().with(*to);
+                attacks::attacks(*to, role.of(us), occupied).contains(king)
+            }
+            // Rare enough that playing the move on a copy is cheaper than handling
+            // the captured pawn and the rook's destination square by hand.
+            Move::EnPassant { .. } | Move::Castle { .. } => {
+                let mut pos = self.pos.clone();
+                pos.play_unchecked(mv);
+                pos.is_check()
+            }
+        }
+    }
+
+    /// Captures (en passant included), promotions and drops. Everything else is quiet, which is
+    /// what pruning, reductions and the quiet history tables work with.
+    pub fn is_noisy(&self, mv: &Move) -> bool {
+        mv.is_capture() || mv.is_promotion() || matches!(mv, Move::Put { .. })
     }
 
     pub fn capture_moves(&self) -> MoveList {
         self.pos.capture_moves()
     }
 
-    pub fn state(&self) -> Chess {
-        self.pos.clone()
+    /// Piece placement, for read-only queries that don't need a copy of the position.
+    pub fn board(&self) -> &shakmaty::Board {
+        self.pos.board()
+    }
+
+    pub fn piece_on(&self, square: Square) -> Option<Role> {
+        self.pos.board().role_at(square)
+    }
+
+    pub fn color_on(&self, square: Square) -> Option<Color> {
+        self.pos.board().color_at(square)
+    }
+
+    /// Pieces of `color` attacking `square` with the current occupancy, sliders included.
+    pub fn attackers_to(&self, square: Square, color: Color) -> Bitboard {
+        let board = self.pos.board();
+        board.attacks_to(square, color, board.occupied())
+    }
+
+    /// Material `color` wins by capturing on `square` and trading off there, see `see_square`.
+    /// Positive for a hanging or underdefended piece of the other side.
+    pub fn see_square(&self, square: Square, color: Color) -> i32 {
+        see_square(self.pos.board(), square, color)
+    }
+
+    /// Returns the position if it's standard chess.
+    pub fn chess(&self) -> Option<&Chess> {
+        match &self.pos {
+            VariantPosition::Chess(pos) => Some(pos),
+            _ => None,
+        }
     }
 
     pub fn set_ply(&mut self, ply: usize) {
@@ -102,8 +524,19 @@ impl Board {
         self.ply
     }
 
+    /// Plays a legal move. Nothing is validated in release builds, where an illegal move leaves
+    /// the board in an inconsistent state.
     pub fn make_move<const IN_PLACE: bool>(&mut self, mv: &Move) {
+        #[cfg(debug_assertions)]
+        assert!(
+            self.legal_moves().contains(mv),
+            "Illegal move {} in {}",
+            mv,
+            self.fen()
+        );
         self.state_stack.push(self.pos.clone());
+        self.hash_stack.push(self.hash);
+        self.hash ^= zobrist::state(&self.pos);
         if !IN_PLACE {
             self.nnue.push();
             self.ply += 1;
@@ -118,6 +551,7 @@ impl Board {
                 to,
                 promotion,
             } => {
+                debug_assert_eq!(self.color_on(*from), Some(stm), "{} in {}", mv, self.fen());
                 self.remove_piece(stm, *role, *from);
 
                 if let Some(capture) = capture {
@@ -135,7 +569,7 @@ impl Board {
                 self.add_piece(stm, Role::Pawn, *to);
 
                 let target = to.xor(Square::A2);
-                if let Some(capture) = self.pos.board().role_at(target) {
+                if let Some(capture) = self.piece_on(target) {
                     self.remove_piece(stm.other(), capture, target);
                 }
             }
@@ -176,60 +610,171 @@ impl Board {
         }
 
         self.pos.play_unchecked(mv);
-        self.nnue.commit();
+        self.in_check.set(None);
+        self.hash ^= zobrist::state(&self.pos);
+        if self.variant == Variant::Crazyhouse {
+            self.hash = self.full_hash();
+        }
+        // Accumulators of searched moves are only computed once the node evaluates, which
+        // TT cutoffs and draws never do
+        if IN_PLACE {
+            self.nnue.commit();
+        }
 
         self.move_stack.push(Some(mv.clone()));
-        self.history.push(self.get_hash());
+        self.history.push(self.hash);
+        debug_assert!(IN_PLACE || self.nnue.depth() == self.ply);
     }
 
-    pub fn undo_move(&mut self) {
+    /// Takes back the last move made with `make_move` and returns it.
+    pub fn undo_move(&mut self) -> Option<Move> {
         self.nnue.pop();
-        let _mv = self.move_stack.pop();
+        let mv = self.move_stack.pop().flatten();
+        debug_assert!(mv.is_some(), "undo_move after a null move");
         self.pos = self.state_stack.pop().unwrap();
+        self.in_check.set(None);
+        self.hash = self.hash_stack.pop().unwrap();
         self.history.pop();
         self.ply -= 1;
+        debug_assert_eq!(self.nnue.depth(), self.ply);
+        mv
     }
 
     pub fn make_null_move(&mut self) {
         self.state_stack.push(self.pos.clone());
+        self.hash_stack.push(self.hash);
         if let Ok(pos) = self.pos.clone().swap_turn() {
+            self.hash ^= zobrist::state(&self.pos) ^ zobrist::state(&pos);
             self.pos = pos;
+            self.in_check.set(None);
+        }
+        if self.variant == Variant::Crazyhouse {
+            self.hash = self.full_hash();
         }
+        // Keeps the accumulator stack in step with the ply
+        self.nnue.push();
         self.move_stack.push(None);
         self.ply += 1;
     }
 
     pub fn undo_null_move(&mut self) {
         self.pos = self.state_stack.pop().unwrap();
-        let _mv = self.move_stack.pop();
+        self.in_check.set(None);
+        self.hash = self.hash_stack.pop().unwrap();
+        self.nnue.pop();
+        let mv = self.move_stack.pop();
+        debug_assert_eq!(mv, Some(None), "undo_null_move after a regular move");
         self.ply -= 1;
     }
 
+    /// Null moves are kept on the move stack as `None`. An empty stack means no move was made.
     pub fn is_last_move_null(&self) -> bool {
-        self.move_stack.last().is_none()
+        matches!(self.move_stack.last(), Some(None))
     }
 
-    pub fn evaluate(&self) -> i32 {
-        let eval = self.nnue.evaluate(self.pos.turn());
-        eval.clamp(-Score::MATE_BOUND + 1, Score::MATE_BOUND - 1)
+    pub fn evaluate(&mut self) -> i32 {
+        self.nnue.compute();
+        debug_assert!(
+            *self.nnue.accumulators()
+                == refreshed_accumulators(self.pos.board().iter().map(|(square, piece)| (
+                    piece.color,
+                    piece.role,
+                    square
+                ))),
+            "Incremental accumulators drifted from a full refresh in {}",
+            self.fen()
+        );
+
+        let mut eval = self.nnue.evaluate(self.pos.turn());
+        let strong = if eval > 0 {
+            self.pos.turn()
+        } else {
+            !self.pos.turn()
+        };
+        eval = eval * self.scale_factor(strong) / SCALE_NORMAL;
+        eval += self.mating_bonus();
+        // Fade towards a draw as the fifty-move rule approaches, so that progress is preferred
+        let clock = self.pos.halfmoves().min(100) as i32;
+        eval = eval * (100 - clock) / 100;
+        eval.clamp(-Score::MAX_EVAL, Score::MAX_EVAL)
+    }
+
+    /// Network output from the side to move's perspective, before clamping.
+    pub fn raw_evaluate(&mut self) -> i32 {
+        self.nnue.compute();
+        self.nnue.evaluate(self.pos.turn())
+    }
+
+    /// How much each non-king piece adds to the evaluation from White's perspective,
+    /// measured by removing it from the board and refreshing the network.
+    pub fn piece_contributions(&mut self) -> Vec<(Square, Piece, i32)> {
+        self.nnue.compute();
+        let eval = self.nnue.evaluate(Color::White);
+        let board = self.pos.board();
+        (board.occupied() & !board.kings())
+            .into_iter()
+            .filter_map(|square| {
+                let piece = board.piece_at(square)?;
+                let without = refreshed_network(board, Some(square)).evaluate(Color::White);
+                Some((square, piece, eval - without))
+            })
+            .collect()
     }
 
     pub fn legal_moves(&self) -> MoveList {
         self.pos.legal_moves()
     }
 
+    pub fn is_legal(&self, mv: &Move) -> bool {
+        self.pos.is_legal(mv)
+    }
+
+    /// Crazyhouse positions are never drawn by material since pieces can be dropped back in.
+    pub fn is_insufficient_material(&self) -> bool {
+        self.variant == Variant::Standard && self.pos.is_insufficient_material()
+    }
+
+    /// Whether the position has occurred before, which the search already scores as a draw.
     pub fn three_fold(&self) -> bool {
-        let mut cnt = 0;
-        let hash = self.get_hash();
-        for &h in self.history.iter().rev() {
-            if h == hash {
-                cnt += 1;
-                if cnt > 1 {
-                    return true;
-                }
-            }
-        }
-        false
+        self.repetition_count() > 1
+    }
+
+    /// How many times the current position has occurred in the game, itself included. A
+    /// capture or pawn move can't be undone, so the scan stops at the halfmove clock.
+    pub fn repetition_count(&self) -> u32 {
+        let window = self.pos.halfmoves() as usize + 1;
+        self.history
+            .iter()
+            .rev()
+            .take(window)
+            .filter(|&&hash| hash == self.hash)
+            .count() as u32
+    }
+
+    /// Plies played since the start of the game, as counted by the full move number.
+    pub fn game_ply(&self) -> usize {
+        2 * (self.pos.fullmoves().get() as usize - 1) + self.pos.turn().fold_wb(0, 1)
+    }
+
+    /// Whether either side could claim a draw right now, by the fifty-move rule or because
+    /// the position has occurred three times. Unlike `three_fold`, a single earlier occurrence
+    /// isn't enough, since this is about the actual game rather than lines in the search.
+    pub fn is_claimable_draw(&self) -> bool {
+        self.pos.halfmoves() >= 100 || self.repetition_count() >= 3
+    }
+
+    pub fn halfmoves(&self) -> u32 {
+        self.pos.halfmoves()
+    }
+
+    /// Whether any position repeats within the last few reversible plies.
+    pub fn has_recent_repetition(&self) -> bool {
+        let window = (self.pos.halfmoves() as usize + 1).min(REPETITION_WINDOW);
+        let recent = &self.history[self.history.len().saturating_sub(window)..];
+        recent
+            .iter()
+            .enumerate()
+            .any(|(i, hash)| recent[i + 1..].contains(hash))
     }
 
     pub fn set_eval(&mut self, ply: usize, eval: i32) {
@@ -249,19 +794,745 @@ impl Board {
 
     fn add_piece(&mut self, color: Color, piece: Role, square: Square) {
         self.nnue.activate(color, piece, square);
+        self.hash ^= zobrist::piece(color, piece, square);
     }
 
     fn remove_piece(&mut self, color: Color, piece: Role, square: Square) {
         self.nnue.deactivate(color, piece, square);
+        self.hash ^= zobrist::piece(color, piece, square);
+    }
+}
+
+fn position_hash(pos: &VariantPosition, variant: Variant) -> u64 {
+    match variant {
+        Variant::Standard => zobrist::full(pos),
+        // Pockets and promoted pieces aren't covered by the incremental keys
+        Variant::Crazyhouse => pos.zobrist_hash::<Zobrist64>(EnPassantMode::Legal).0,
+    }
+}
+
+/// Parses a FEN into a legal position under `variant`.
+fn parse_position(fen: &str, variant: Variant) -> Result<VariantPosition, FenError> {
+    let fen: Fen = fen.parse().map_err(FenError::Invalid)?;
+    VariantPosition::from_setup(variant.into(), fen.into_setup(), CastlingMode::Standard)
+        .map_err(|err| FenError::Illegal(Box::new(err)))
+}
+
+/// Builds the network accumulators from scratch, optionally leaving out the piece on `skip`.
+fn refreshed_network(board: &shakmaty::Board, skip: Option<Square>) -> Network {
+    let mut nnue = Network::default();
+    refresh(&mut nnue, board, skip);
+    nnue
+}
+
+/// Accumulates every piece of the board, except the one on `skip`, onto a freshly reset network.
+fn refresh(nnue: &mut Network, board: &shakmaty::Board, skip: Option<Square>) {
+    for color in [Color::White, Color::Black] {
+        for piece in Role::ALL {
+            for square in board.by_color(color) & board.by_role(piece) {
+                if Some(square) != skip {
+                    nnue.accumulate(color, piece, square);
+                }
+            }
+        }
+    }
+}
+
+fn mirror_fen(fen: &str) -> String {
+    fn swap_case(text: &str) -> String {
+        text.chars()
+            .map(|c| {
+                if c.is_ascii_uppercase() {
+                    c.to_ascii_lowercase()
+                } else {
+                    c.to_ascii_uppercase()
+                }
+            })
+            .collect()
+    }
+
+    let mut fields: Vec<String> = fen.split_whitespace().map(str::to_string).collect();
+    // Crazyhouse pockets trail the placement in brackets
+    let (placement, pocket) = fields[0].split_at(fields[0].find('[').unwrap_or(fields[0].len()));
+    let ranks: Vec<&str> = placement.split('/').rev().collect();
+    fields[0] = swap_case(&ranks.join("/")) + &swap_case(pocket);
+
+    if let Some(turn) = fields.get_mut(1) {
+        *turn = if turn == "w" { "b" } else { "w" }.to_string();
+    }
+    if let Some(castling) = fields.get_mut(2) {
+        // White's rights are listed first
+        let (white, black): (String, String) = swap_case(castling)
+            .chars()
+            .partition(char::is_ascii_uppercase);
+        *castling = white + &black;
     }
+    if let Some(ep) = fields.get_mut(3) {
+        *ep = ep
+            .chars()
+            .map(|c| match c {
+                '3' => '6',
+                '6' => '3',
+                _ => c,
+            })
+            .collect();
+    }
+    fields.join(" ")
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::board::Board;
+    use crate::board::{refreshed_network, Board, FenError, MoveError, Variant, SCALE_NORMAL};
+    use crate::types::Score;
+    use shakmaty::{Bitboard, Color, Move, Position, Role, Square};
     #[test]
     fn test_nnue() {
         let mut board = Board::starting_position();
         assert_eq!(board.evaluate(), 48);
     }
+
+    #[test]
+    fn test_set_from_fen() {
+        let fen = "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4";
+        let mut board = Board::starting_position();
+        board.play_uci("e2e4");
+        let mv = board.legal_moves()[0].clone();
+        board.make_move::<false>(&mv);
+        board.make_null_move();
+
+        board.set_from_fen(fen).unwrap();
+        let mut fresh = Board::new(fen).unwrap();
+        assert_eq!(board.fen(), fresh.fen());
+        assert_eq!(board.get_hash(), fresh.get_hash());
+        assert_eq!(board.ply(), 0);
+        assert_eq!(board.nnue.depth(), 0);
+        assert_eq!(board.last_move(), None);
+        assert_eq!(board.history, fresh.history);
+        assert!(board.state_stack.is_empty() && board.hash_stack.is_empty());
+        assert_eq!(board.evaluate(), fresh.evaluate());
+
+        // A bad FEN leaves the position alone
+        assert!(matches!(
+            board.set_from_fen("not a fen"),
+            Err(FenError::Invalid(_))
+        ));
+        assert_eq!(board.fen(), fen);
+        // So does a well formed one that isn't a legal position
+        for illegal in [
+            "8/8/8/8/8/8/8/4K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/4RK2 w - - 0 1",
+        ] {
+            assert!(matches!(
+                board.set_from_fen(illegal),
+                Err(FenError::Illegal(_))
+            ));
+            assert_eq!(board.fen(), fen);
+        }
+        assert!(matches!(
+            Board::new("8/8/8/8/8/8/8/4K3 w - - 0 1"),
+            Err(FenError::Illegal(_))
+        ));
+    }
+
+    #[test]
+    fn test_piece_contributions_after_move() {
+        // The accumulators of a move made in search are only computed once needed
+        let mut board = Board::starting_position();
+        let mv = board.legal_moves()[0].clone();
+        board.make_move::<false>(&mv);
+        let mut fresh = Board::new(&board.fen()).unwrap();
+        assert_eq!(board.piece_contributions(), fresh.piece_contributions());
+    }
+
+    #[test]
+    fn test_clone_is_independent() {
+        let mut board = Board::starting_position();
+        board.play_uci("e2e4");
+        let before = board.evaluate();
+
+        let mut clone = board.clone();
+        assert_eq!(clone.evaluate(), before);
+        let mv = clone.legal_moves()[0].clone();
+        clone.make_move::<false>(&mv);
+        clone.evaluate();
+
+        assert_eq!(board.evaluate(), before);
+        clone.undo_move();
+        assert_eq!(clone.evaluate(), before);
+    }
+
+    #[test]
+    fn test_lazy_accumulators() {
+        let mut board =
+            Board::new("r3k2r/pPppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/P1PBBPPP/R3K2R w KQkq - 0 1")
+                .unwrap();
+        let mut evals = Vec::new();
+        for uci in ["e1g1", "e8g8", "a2a4", "b4a3", "b7a8q"] {
+            let mv = board
+                .legal_moves()
+                .into_iter()
+                .find(|mv| board.to_uci(mv).to_string() == uci)
+                .unwrap();
+            board.make_move::<false>(&mv);
+            evals.push(Board::new(&board.fen()).unwrap().evaluate());
+        }
+        board.make_null_move();
+        // Only the last state is computed, from the root through every skipped state
+        assert_eq!(
+            board.raw_evaluate(),
+            Board::new(&board.fen()).unwrap().raw_evaluate()
+        );
+        board.undo_null_move();
+        for expected in evals.into_iter().rev() {
+            assert_eq!(board.evaluate(), expected);
+            board.undo_move();
+        }
+        assert_eq!(
+            board.nnue.accumulators(),
+            refreshed_network(board.pos.board(), None).accumulators()
+        );
+    }
+
+    #[test]
+    fn test_gives_check() {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "4k3/8/8/3pP3/8/8/8/R3K2B w Q d6 0 1",
+            "3k4/1P6/8/8/8/8/8/4K2R w K - 0 1",
+        ];
+        for fen in fens {
+            let board = Board::new(fen).unwrap();
+            for mv in board.legal_moves() {
+                let mut pos = board.pos.clone();
+                pos.play_unchecked(&mv);
+                assert_eq!(board.gives_check(&mv), pos.is_check(), "{fen} {mv}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_truncated_fen() {
+        // The FENs of the SEE tests, which stop after the en passant field
+        for fen in [
+            "1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - ",
+            "1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - ",
+        ] {
+            let board = Board::new(fen).unwrap();
+            assert_eq!(board.fen(), format!("{}0 1", fen));
+            assert_eq!(board.halfmoves(), 0);
+        }
+        let board = Board::new("4k3/8/8/8/8/8/8/4K3 b").unwrap();
+        assert_eq!(board.fen(), "4k3/8/8/8/8/8/8/4K3 b - - 0 1");
+        assert_eq!(board.game_ply(), 1);
+    }
+
+    #[test]
+    fn test_square_accessors() {
+        let board = Board::starting_position();
+        assert_eq!(board.piece_on(Square::E1), Some(Role::King));
+        assert_eq!(board.color_on(Square::E1), Some(Color::White));
+        assert_eq!(board.piece_on(Square::D8), Some(Role::Queen));
+        assert_eq!(board.color_on(Square::D8), Some(Color::Black));
+        assert_eq!(board.piece_on(Square::E4), None);
+        assert_eq!(board.color_on(Square::E4), None);
+        assert_eq!(board.board().occupied().count(), 32);
+    }
+
+    #[test]
+    fn test_try_play_uci() {
+        let mut board = Board::starting_position();
+        assert_eq!(board.try_play_uci("e2e4"), Ok(()));
+        assert_eq!(
+            board.try_play_uci("e2e4"),
+            Err(MoveError::Illegal("e2e4".to_string()))
+        );
+        assert_eq!(
+            board.try_play_uci("e9e5"),
+            Err(MoveError::Invalid("e9e5".to_string()))
+        );
+        // Failed moves leave the position as it was
+        assert_eq!(board.turn(), Color::Black);
+        assert_eq!(board.try_play_uci("e7e5"), Ok(()));
+        assert_eq!(board.turn(), Color::White);
+    }
+
+    #[test]
+    fn test_try_play_uci_castling() {
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        for (uci, king, rook) in [
+            ("e1g1", Square::G1, Square::F1),
+            ("e1h1", Square::G1, Square::F1),
+            ("e1c1", Square::C1, Square::D1),
+            ("e1a1", Square::C1, Square::D1),
+        ] {
+            let mut board = Board::new(fen).unwrap();
+            assert_eq!(board.try_play_uci(uci), Ok(()), "{uci}");
+            assert_eq!(board.piece_on(king), Some(Role::King), "{uci}");
+            assert_eq!(board.piece_on(rook), Some(Role::Rook), "{uci}");
+            assert_eq!(board.get_hash(), board.full_hash(), "{uci}");
+        }
+        // Without the right, taking the own rook is just illegal
+        let mut board = Board::new("r3k2r/8/8/8/8/8/8/R3K2R w Qkq - 0 1").unwrap();
+        assert_eq!(
+            board.try_play_uci("e1h1"),
+            Err(MoveError::Illegal("e1h1".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_line_to_san() {
+        let line = |board: &Board, ucis: &[&str]| {
+            let mut scratch = board.clone();
+            ucis.iter()
+                .map(|uci| {
+                    scratch.play_uci(uci);
+                    scratch.last_move().unwrap()
+                })
+                .collect::<Vec<_>>()
+        };
+        let board = Board::starting_position();
+
+        // The queen's knight needs its file once the other one can reach d2 too
+        let moves = line(&board, &["g1f3", "g8f6", "d2d3", "d7d6", "b1d2"]);
+        assert_eq!(board.line_to_san(&moves), "Nf3 Nf6 d3 d6 Nbd2");
+
+        let moves = line(
+            &board,
+            &["e2e4", "e7e5", "d1h5", "b8c6", "f1c4", "g8f6", "h5f7"],
+        );
+        assert_eq!(board.line_to_san(&moves), "e4 e5 Qh5 Nc6 Bc4 Nf6 Qxf7#");
+        assert_eq!(board.line_to_san(&moves[..1]), "e4");
+        assert_eq!(board.line_to_san(&[]), "");
+
+        let board = Board::new("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        let moves = line(&board, &["e1g1", "e8c8"]);
+        assert_eq!(board.line_to_san(&moves), "O-O O-O-O");
+    }
+
+    #[test]
+    fn test_last_move() {
+        let mut board = Board::starting_position();
+        assert_eq!(board.last_move(), None);
+        assert!(!board.is_last_move_null());
+
+        board.play_uci("e2e4");
+        board.play_uci("e7e5");
+        let uci = |mv: Option<Move>| mv.map(|mv| board.to_uci(&mv).to_string());
+        assert_eq!(uci(board.last_move()).as_deref(), Some("e7e5"));
+        assert_eq!(uci(board.move_before_last()).as_deref(), Some("e2e4"));
+        assert_eq!(board.tail_move(3), None);
+
+        board.make_null_move();
+        assert!(board.is_last_move_null());
+        assert_eq!(board.last_move(), None);
+        assert!(board.move_before_last().is_some());
+        board.undo_null_move();
+        assert!(!board.is_last_move_null());
+
+        let mv = board.legal_moves()[0].clone();
+        board.make_move::<false>(&mv);
+        assert_eq!(board.undo_move(), Some(mv));
+        assert!(board
+            .last_move()
+            .is_some_and(|last| board.to_uci(&last).to_string() == "e7e5"));
+    }
+
+    #[test]
+    fn test_claimable_draw() {
+        let mut board = Board::starting_position();
+        for _ in 0..2 {
+            for uci in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+                assert!(!board.is_claimable_draw());
+                board.play_uci(uci);
+            }
+        }
+        assert!(board.three_fold());
+        assert!(board.is_claimable_draw());
+
+        let board = Board::new("4k3/8/8/8/8/8/8/3QK3 w - - 99 80").unwrap();
+        assert!(!board.is_claimable_draw());
+        let board = Board::new("4k3/8/8/8/8/8/8/3QK3 w - - 100 80").unwrap();
+        assert!(board.is_claimable_draw());
+    }
+
+    #[test]
+    fn test_repetition_count() {
+        let mut board = Board::starting_position();
+        assert_eq!(board.repetition_count(), 1);
+        for count in 2..=3 {
+            for uci in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+                board.play_uci(uci);
+            }
+            assert_eq!(board.repetition_count(), count);
+        }
+        // The same squares with the other side to move are a different position
+        board.play_uci("g1f3");
+        assert_eq!(board.repetition_count(), 3);
+        board.play_uci("g8f6");
+        assert_eq!(board.repetition_count(), 3);
+        board.play_uci("e2e4");
+        assert_eq!(board.repetition_count(), 1);
+
+        // Nothing before the last pawn move is looked at, even a matching hash
+        let mut board = Board::new("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1").unwrap();
+        board.play_uci("e2e4");
+        for uci in ["e8d8", "e1d1", "d8e8", "d1e1"] {
+            board.play_uci(uci);
+        }
+        assert_eq!(board.repetition_count(), 2);
+        board.history.insert(0, board.get_hash());
+        assert_eq!(board.repetition_count(), 2);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Illegal move")]
+    fn test_make_move_rejects_illegal() {
+        // Pinned against the king
+        let mut board = Board::new("4k3/8/8/8/4r3/8/4N3/4K3 w - - 0 1").unwrap();
+        let pinned = Move::Normal {
+            role: Role::Knight,
+            from: Square::E2,
+            capture: None,
+            to: Square::C3,
+            promotion: None,
+        };
+        board.make_move::<false>(&pinned);
+    }
+
+    #[test]
+    fn test_in_check_cached_per_position() {
+        let mut board = Board::new("4k3/8/8/8/8/8/8/R3K3 w - - 0 1").unwrap();
+        assert!(!board.in_check());
+        let moves = board.legal_moves();
+        let check = moves.iter().find(|mv| mv.to() == Square::A8).unwrap();
+        let quiet = moves.iter().find(|mv| mv.to() == Square::A7).unwrap();
+
+        board.make_move::<false>(check);
+        assert!(board.in_check());
+        assert!(board.in_check());
+        board.undo_move();
+        assert!(!board.in_check());
+        board.make_move::<false>(quiet);
+        assert!(!board.in_check());
+        board.undo_move();
+
+        board.make_null_move();
+        assert!(!board.in_check());
+        board.undo_null_move();
+        board
+            .set_from_fen("R3k3/8/8/8/8/8/8/4K3 b - - 0 1")
+            .unwrap();
+        assert!(board.in_check());
+    }
+
+    #[test]
+    fn test_see_square() {
+        // A knight left hanging to a pawn
+        let board = Board::new("4k3/8/8/3n4/4P3/8/8/4K3 w - - 0 1").unwrap();
+        assert_eq!(board.see_square(Square::D5, Color::White), 400);
+        assert_eq!(board.see_square(Square::E4, Color::Black), 0);
+        assert_eq!(board.see_square(Square::E4, Color::White), 0);
+        assert_eq!(board.see_square(Square::E5, Color::White), 0);
+
+        // Defended, the pawn still wins the exchange but a rook doesn't want it
+        let board = Board::new("4k3/8/2p5/3n4/4P3/8/8/4K3 w - - 0 1").unwrap();
+        assert_eq!(board.see_square(Square::D5, Color::White), 300);
+        let board = Board::new("4k3/8/2p5/3n4/8/8/8/3RK3 w - - 0 1").unwrap();
+        assert_eq!(board.see_square(Square::D5, Color::White), 0);
+
+        // The second rook only joins through the first
+        let board = Board::new("3rk3/8/8/3n4/8/8/3R4/3R2K1 w - - 0 1").unwrap();
+        assert_eq!(board.see_square(Square::D5, Color::White), 400);
+        let board = Board::new("3rk3/8/8/3n4/8/8/8/3R2K1 w - - 0 1").unwrap();
+        assert_eq!(board.see_square(Square::D5, Color::White), 0);
+    }
+
+    #[test]
+    fn test_attackers_to() {
+        let board = Board::new("4k3/8/8/3q4/5N2/8/4P3/3RK3 w - - 0 1").unwrap();
+        let white = board.attackers_to(Square::D5, Color::White);
+        assert_eq!(
+            white,
+            Bitboard::from(Square::D1) | Bitboard::from(Square::F4)
+        );
+        // Sliders see along open lines only, so the queen reaches d2 and nothing past it
+        assert_eq!(
+            board.attackers_to(Square::D2, Color::Black),
+            Bitboard::from(Square::D5)
+        );
+        assert_eq!(
+            board.attackers_to(Square::D2, Color::White),
+            Bitboard::from(Square::D1) | Bitboard::from(Square::E1)
+        );
+        assert!(board.attackers_to(Square::A8, Color::White).is_empty());
+    }
+
+    #[test]
+    fn test_is_noisy() {
+        let board = Board::new("r3k3/1P6/8/3pP3/8/8/8/4K2R w K d6 0 1").unwrap();
+        let noisy = |uci: &str| {
+            let mv = board
+                .legal_moves()
+                .into_iter()
+                .find(|mv| board.to_uci(mv).to_string() == uci)
+                .unwrap();
+            board.is_noisy(&mv)
+        };
+        assert!(noisy("b7a8q"));
+        assert!(noisy("b7b8q"));
+        assert!(noisy("b7b8n"));
+        assert!(noisy("b7a8r"));
+        assert!(noisy("e5d6"));
+        assert!(!noisy("e5e6"));
+        assert!(!noisy("e1g1"));
+        assert!(!noisy("h1h7"));
+
+        let board =
+            Board::with_variant("4k3/8/8/8/8/8/8/4K3[N] w - - 0 1", Variant::Crazyhouse).unwrap();
+        let moves = board.legal_moves();
+        assert!(moves
+            .iter()
+            .all(|mv| board.is_noisy(mv) == matches!(mv, Move::Put { .. })));
+    }
+
+    #[test]
+    fn test_variant_insufficient_material() {
+        let fen = "8/8/4k3/8/8/3K4/8/8 w - - 0 1";
+        assert!(Board::new(fen).unwrap().is_insufficient_material());
+        let board = Board::with_variant(fen, Variant::Crazyhouse).unwrap();
+        assert!(!board.is_insufficient_material());
+    }
+
+    #[test]
+    fn test_incremental_hash() {
+        let mut board =
+            Board::new("r3k2r/pPppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/P1PBBPPP/R3K2R w KQkq - 0 1")
+                .unwrap();
+        let root = board.get_hash();
+        let moves = ["e1g1", "e8g8", "a2a4", "b4a3", "b7a8q", "h3g2"];
+        for uci in moves {
+            let mv = board
+                .legal_moves()
+                .into_iter()
+                .find(|mv| board.to_uci(mv).to_string() == uci)
+                .unwrap();
+            board.make_move::<false>(&mv);
+            assert_eq!(board.get_hash(), board.full_hash(), "{uci}");
+        }
+        for _ in moves {
+            board.undo_move();
+        }
+        assert_eq!(board.get_hash(), root);
+
+        board.make_null_move();
+        assert_eq!(board.get_hash(), board.full_hash());
+        board.undo_null_move();
+        assert_eq!(board.get_hash(), root);
+    }
+
+    #[test]
+    fn test_hash_after() {
+        // Castling both ways, rook captures losing rights, en passant, promotions and checks
+        for fen in [
+            "r3k2r/pPppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/P1PBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/pPppqpb1/bn2pnp1/3PN3/Pp2P3/2N2Q1p/2PBBPPP/R3K2R b KQkq a3 0 1",
+            "rnbqkb1r/pp1p1ppp/2p5/4P3/2B5/8/PPP1NnPP/RNBQK2R w KQkq - 0 6",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        ] {
+            let mut board = Board::new(fen).unwrap();
+            for mv in &board.legal_moves() {
+                let hash = board.hash_after(mv);
+                board.make_move::<false>(mv);
+                assert_eq!(hash, board.get_hash(), "{fen} {mv}");
+                board.undo_move();
+            }
+        }
+    }
+
+    #[test]
+    fn test_hash_excluding() {
+        let board = Board::starting_position();
+        let moves = board.legal_moves();
+        assert_eq!(board.hash_excluding(&None), board.get_hash());
+
+        let mut keys: Vec<u64> = moves
+            .iter()
+            .map(|mv| board.hash_excluding(&Some(mv.clone())))
+            .collect();
+        keys.push(board.get_hash());
+        keys.sort_unstable();
+        keys.dedup();
+        assert_eq!(keys.len(), moves.len() + 1);
+
+        let excluded = Some(moves[0].clone());
+        let key = board.hash_excluding(&excluded);
+        assert_eq!(key ^ super::zobrist::excluded(&moves[0]), board.get_hash());
+    }
+
+    #[test]
+    fn test_non_pawn_material() {
+        let board = Board::starting_position();
+        assert_eq!(board.non_pawn_material(Color::White), 4100);
+        assert_eq!(board.non_pawn_material(Color::Black), 4100);
+        assert!(!board.is_opposite_bishops());
+
+        let board = Board::new("8/4k3/4b3/1p3P2/8/2B1K3/8/8 w - - 0 1").unwrap();
+        assert_eq!(board.non_pawn_material(Color::White), 400);
+        assert!(board.is_opposite_bishops());
+
+        let board = Board::new("8/4k3/3b4/1p3P2/8/2B1K3/8/8 w - - 0 1").unwrap();
+        assert!(!board.is_opposite_bishops());
+
+        // Knight against a light-squared bishop
+        let board = Board::new("8/4k3/4b3/1p3P2/8/2N1K3/8/8 w - - 0 1").unwrap();
+        assert_eq!(board.non_pawn_material(Color::White), 400);
+        assert!(!board.is_opposite_bishops());
+        assert_eq!(board.scale_factor(Color::White), SCALE_NORMAL);
+    }
+
+    #[test]
+    fn test_scale_factor() {
+        let board = Board::starting_position();
+        assert_eq!(board.scale_factor(Color::White), SCALE_NORMAL);
+
+        // KRvKR and KRvKB
+        let board = Board::new("8/8/3k4/3r4/8/3R4/3K4/8 w - - 0 1").unwrap();
+        assert_eq!(board.scale_factor(Color::White), 4);
+        let board = Board::new("8/8/3k4/3b4/8/3R4/3K4/8 w - - 0 1").unwrap();
+        assert_eq!(board.scale_factor(Color::White), 4);
+        // KQvKR is a win
+        let board = Board::new("8/8/3k4/3r4/8/3Q4/3K4/8 w - - 0 1").unwrap();
+        assert_eq!(board.scale_factor(Color::White), SCALE_NORMAL);
+
+        // Opposite colored bishops a pawn up, then three pawns up
+        let board = Board::new("8/4k3/4b3/1p3P2/8/2B1K3/P7/8 w - - 0 1").unwrap();
+        assert_eq!(board.scale_factor(Color::White), 16);
+        let board = Board::new("8/4k3/4b3/5P2/8/2B1K3/PPP5/8 w - - 0 1").unwrap();
+        assert_eq!(board.scale_factor(Color::White), 32);
+
+        let board = Board::with_variant("8/8/3k4/3r4/8/3R4/3K4/8[] w - - 0 1", Variant::Crazyhouse)
+            .unwrap();
+        assert_eq!(board.scale_factor(Color::White), SCALE_NORMAL);
+    }
+
+    #[test]
+    fn test_material_key() {
+        let board = Board::starting_position();
+        assert_eq!(board.piece_count(), 32);
+
+        // Same material in different places, then one white pawn less
+        let kiwipete =
+            Board::new("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+                .unwrap();
+        assert_eq!(kiwipete.material_key(), board.material_key());
+        let board = Board::new("rnbqkbnr/pppppppp/8/8/8/8/1PPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
+        assert_eq!(board.piece_count(), 31);
+        assert_ne!(board.material_key(), kiwipete.material_key());
+
+        // Colors are told apart, KRvK isn't KvKR
+        let white = Board::new("4k3/8/8/8/8/8/8/R3K3 w - - 0 1").unwrap();
+        let black = Board::new("r3k3/8/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        assert_eq!(white.piece_count(), 3);
+        assert_ne!(white.material_key(), black.material_key());
+    }
+
+    #[test]
+    fn test_evaluate_within_max_eval() {
+        // Lopsided enough that the network and the mating bonus together could overshoot
+        for fen in [
+            "7k/8/5K2/8/8/8/QQQQQ3/QQQQ4 w - - 0 1",
+            "7k/8/5K2/8/8/8/QQQQQ3/QQQQ4 b - - 0 1",
+            "qqqq4/qqqqq3/8/8/8/5k2/8/7K w - - 0 1",
+        ] {
+            let eval = Board::new(fen).unwrap().evaluate();
+            assert!(eval.abs() <= Score::MAX_EVAL, "{fen} {eval}");
+        }
+    }
+
+    #[test]
+    fn test_mating_bonus() {
+        let bonus = |fen: &str| Board::new(fen).unwrap().mating_bonus();
+        // KQvK: the lone king in the corner beats the center, and closer kings beat far ones
+        assert!(bonus("7k/8/8/8/8/8/2Q5/K7 w - - 0 1") > bonus("8/8/8/3k4/8/8/2Q5/K7 w - - 0 1"));
+        assert!(bonus("7k/8/5K2/8/8/8/2Q5/8 w - - 0 1") > bonus("7k/8/8/8/8/8/2Q5/K7 w - - 0 1"));
+        assert_eq!(
+            bonus("7k/8/8/8/8/8/2Q5/K7 b - - 0 1"),
+            -bonus("7k/8/8/8/8/8/2Q5/K7 w - - 0 1")
+        );
+        // KBNK with a dark squared bishop mates on a1 or h8, not on a8
+        assert!(bonus("7k/8/8/8/8/8/8/K1BN4 w - - 0 1") > bonus("k7/8/8/8/8/8/8/K1BN4 w - - 0 1"));
+        // No mating material, or a defender with more than a king
+        assert_eq!(bonus("7k/8/8/8/8/8/8/KNN5 w - - 0 1"), 0);
+        assert_eq!(bonus("7k/7p/8/8/8/8/2Q5/K7 w - - 0 1"), 0);
+        assert_eq!(bonus("7k/8/8/8/8/8/1P6/K7 w - - 0 1"), 0);
+    }
+
+    #[test]
+    fn test_fifty_move_scaling() {
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq -";
+        let fresh = Board::new(&format!("{fen} 0 1")).unwrap().evaluate();
+        let halfway = Board::new(&format!("{fen} 50 40")).unwrap().evaluate();
+        assert_eq!(halfway, fresh / 2);
+
+        let mut limit = Board::new(&format!("{fen} 100 70")).unwrap();
+        assert_eq!(limit.evaluate(), Score::DRAW);
+    }
+
+    #[test]
+    fn test_mirror() {
+        let mut board =
+            Board::new("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQq f6 0 3").unwrap();
+        let mut mirrored = board.mirror();
+        assert_eq!(
+            mirrored.fen(),
+            "rnbqkbnr/pppp1ppp/8/8/3PpP2/8/PPP1P1PP/RNBQKBNR b Qkq f3 0 3"
+        );
+        assert_eq!(mirrored.mirror().fen(), board.fen());
+        assert_eq!(mirrored.evaluate(), board.evaluate());
+    }
+
+    #[test]
+    fn test_evaluate_perspective() {
+        // Scores are from the side to move, so the mirror scores the same and White's view
+        // of it flips sign
+        for fen in [
+            "r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4",
+            "r1bqkb1r/pppp1Qpp/2n2n2/4p3/2B1P3/8/PPPP1PPP/RNB1K1NR b KQkq - 0 4",
+            "8/5pk1/6p1/3R4/8/6P1/5PK1/2r5 b - - 3 40",
+            "4k3/8/8/8/8/8/8/3QK3 w - - 0 1",
+        ] {
+            let mut board = Board::new(fen).unwrap();
+            let mut mirrored = board.mirror();
+            assert_eq!(mirrored.evaluate(), board.evaluate(), "{fen}");
+            assert_eq!(mirrored.raw_evaluate(), board.raw_evaluate(), "{fen}");
+
+            let white = |board: &mut Board| match board.turn() {
+                Color::White => board.evaluate(),
+                Color::Black => -board.evaluate(),
+            };
+            assert_eq!(white(&mut mirrored), -white(&mut board), "{fen}");
+        }
+
+        // An extra queen is good for whoever has it, whichever side is to move
+        let mut white_to_move = Board::new("4k3/8/8/8/8/8/8/3QK3 w - - 0 1").unwrap();
+        let mut black_to_move = Board::new("4k3/8/8/8/8/8/8/3QK3 b - - 0 1").unwrap();
+        assert!(white_to_move.raw_evaluate() > 0);
+        assert!(black_to_move.raw_evaluate() < 0);
+    }
+
+    #[test]
+    fn test_recent_repetition() {
+        let mut board = Board::starting_position();
+        for mv in ["g1f3", "g8f6", "f3g1"] {
+            board.play_uci(mv);
+        }
+        assert!(!board.has_recent_repetition());
+        board.play_uci("f6g8");
+        assert!(board.has_recent_repetition());
+        board.play_uci("e2e4");
+        assert!(!board.has_recent_repetition());
+    }
 }
@@ -0,0 +1,86 @@
+use shakmaty::{Color, EnPassantMode, Move, Position, Role, Square};
+
+static PIECE_KEYS: [u64; 768] = keys(0x1F2E_3D4C_5B6A_7988);
+static CASTLING_KEYS: [u64; 64] = keys(0x2B3C_4D5E_6F70_8192);
+static EN_PASSANT_KEYS: [u64; 8] = keys(0x3A4B_5C6D_7E8F_90A1);
+static EXCLUDED_KEYS: [u64; 72 * 64] = keys(0x5D6E_7F80_91A2_B3C4);
+const SIDE_KEY: u64 = keys::<1>(0x4C5D_6E7F_8091_A2B3)[0];
+
+/// Generates pseudo-random keys at compile time with SplitMix64.
+const fn keys<const N: usize>(seed: u64) -> [u64; N] {
+    let mut keys = [0; N];
+    let mut state = seed;
+    let mut i = 0;
+    while i < N {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        keys[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    keys
+}
+
+/// Key of a single piece on a square, toggled whenever the piece is added or removed.
+pub fn piece(color: Color, role: Role, square: Square) -> u64 {
+    PIECE_KEYS[384 * color as usize + 64 * (role as usize - 1) + square as usize]
+}
+
+/// Key of a move excluded from the search of a node. The origin is the from square, or one of
+/// eight slots past the board for drops, and promotions rotate the key by the promoted role.
+pub fn excluded(mv: &Move) -> u64 {
+    let origin = mv
+        .from()
+        .map_or(64 + mv.role() as usize, |from| from as usize);
+    let promotion = mv.promotion().map_or(0, |role| role as u32);
+    EXCLUDED_KEYS[64 * origin + mv.to() as usize].rotate_left(promotion)
+}
+
+/// Key of everything but the piece placement: side to move, castling rights and
+/// the legal en passant file.
+pub fn state<P: Position>(pos: &P) -> u64 {
+    let mut key = 0;
+    for square in pos.castles().castling_rights() {
+        key ^= CASTLING_KEYS[square as usize];
+    }
+    if let Some(square) = pos.ep_square(EnPassantMode::Legal) {
+        key ^= EN_PASSANT_KEYS[square.file() as usize];
+    }
+    if pos.turn() == Color::Black {
+        key ^= SIDE_KEY;
+    }
+    key
+}
+
+/// Key of the state after `mv`, for moves that can't leave an en passant square behind.
+/// Castling rights are lost when their rook square is left or captured on, or the king moves.
+pub fn state_after<P: Position>(pos: &P, mv: &Move) -> u64 {
+    let us = pos.turn();
+    let king_moved = mv.role() == Role::King;
+    let mut key = 0;
+    for square in pos.castles().castling_rights() {
+        let lost = mv.from() == Some(square)
+            || mv.to() == square
+            || (king_moved && pos.board().color_at(square) == Some(us));
+        if !lost {
+            key ^= CASTLING_KEYS[square as usize];
+        }
+    }
+    if us == Color::White {
+        key ^= SIDE_KEY;
+    }
+    key
+}
+
+/// Computes the hash of a position from scratch.
+pub fn full<P: Position>(pos: &P) -> u64 {
+    let board = pos.board();
+    let mut key = state(pos);
+    for square in board.occupied() {
+        if let Some(piece) = board.piece_at(square) {
+            key ^= self::piece(piece.color, piece.role, square);
+        }
+    }
+    key
+}
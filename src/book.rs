@@ -0,0 +1,138 @@
+use crate::board::Board;
+use crate::util::Rng;
+
+use shakmaty::zobrist::{Zobrist64, ZobristHash};
+use shakmaty::{EnPassantMode, Move, Position, Role, Square};
+use std::{fs, io};
+
+const ENTRY_SIZE: usize = 16;
+
+/// A single Polyglot book entry (16 bytes, big-endian).
+#[derive(Clone, Copy)]
+struct Entry {
+    key: u64,
+    mv: u16,
+    weight: u16,
+}
+
+/// Opening book in the Polyglot `.bin` format, sorted by key.
+pub struct Book {
+    entries: Vec<Entry>,
+}
+
+impl Book {
+    pub fn load(path: &str) -> io::Result<Self> {
+        Ok(Self::from_bytes(&fs::read(path)?))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let entries = bytes
+            .chunks_exact(ENTRY_SIZE)
+            .map(|chunk| Entry {
+                key: u64::from_be_bytes(chunk[0..8].try_into().unwrap()),
+                mv: u16::from_be_bytes(chunk[8..10].try_into().unwrap()),
+                weight: u16::from_be_bytes(chunk[10..12].try_into().unwrap()),
+            })
+            .collect();
+        Self { entries }
+    }
+
+    /// Picks a weighted-random book move for the position, or `None` when out of book.
+    /// Only standard chess is supported, since Polyglot keys don't cover pockets.
+    pub fn probe(&self, board: &Board, rng: &mut Rng) -> Option<Move> {
+        let pos = board.chess()?;
+        // Polyglot keys are the ones shakmaty uses for 64-bit Zobrist hashes
+        let key = pos.zobrist_hash::<Zobrist64>(EnPassantMode::Legal).0;
+
+        let start = self.entries.partition_point(|entry| entry.key < key);
+        let moves = pos.legal_moves();
+        let candidates: Vec<(Move, u32)> = self.entries[start..]
+            .iter()
+            .take_while(|entry| entry.key == key)
+            .filter_map(|entry| {
+                let mv = moves.iter().find(|mv| encode(mv) == entry.mv & 0x7fff)?;
+                Some((mv.clone(), u32::from(entry.weight)))
+            })
+            .collect();
+
+        let total: u32 = candidates.iter().map(|(_, weight)| weight).sum();
+        if total == 0 {
+            return candidates.first().map(|(mv, _)| mv.clone());
+        }
+
+        let mut pick = rng.below(total);
+        for (mv, weight) in candidates {
+            if pick < weight {
+                return Some(mv);
+            }
+            pick -= weight;
+        }
+        None
+    }
+}
+
+/// Encodes a move the way Polyglot does. Castling is stored as the king capturing its own rook.
+fn encode(mv: &Move) -> u16 {
+    fn square(square: Square) -> u16 {
+        (square.rank() as u16) << 3 | square.file() as u16
+    }
+
+    let (from, to, promotion) = match mv {
+        Move::Normal {
+            from,
+            to,
+            promotion,
+            ..
+        } => (*from, *to, *promotion),
+        Move::EnPassant { from, to } => (*from, *to, None),
+        Move::Castle { king, rook } => (*king, *rook, None),
+        Move::Put { .. } => return 0,
+    };
+    let promotion = match promotion {
+        Some(Role::Knight) => 1,
+        Some(Role::Bishop) => 2,
+        Some(Role::Rook) => 3,
+        Some(Role::Queen) => 4,
+        _ => 0,
+    };
+    promotion << 12 | square(from) << 6 | square(to)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(key: u64, mv: u16, weight: u16) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend(key.to_be_bytes());
+        bytes.extend(mv.to_be_bytes());
+        bytes.extend(weight.to_be_bytes());
+        bytes.extend(0u32.to_be_bytes());
+        bytes
+    }
+
+    #[test]
+    fn test_book_probe() {
+        // e2e4 from the starting position
+        let book = Book::from_bytes(&entry(0x463b_9618_1691_fc9c, 0x031c, 1));
+        let board = Board::starting_position();
+        let mv = book.probe(&board, &mut Rng::new(0)).unwrap();
+        assert_eq!(board.to_uci(&mv).to_string(), "e2e4");
+
+        let board =
+            Board::new("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1").unwrap();
+        assert!(book.probe(&board, &mut Rng::new(0)).is_none());
+    }
+
+    #[test]
+    fn test_book_castling() {
+        let board = Board::new("r3k2r/pppppppp/8/8/8/8/PPPPPPPP/R3K2R w KQkq - 0 1").unwrap();
+        let castle = board
+            .legal_moves()
+            .into_iter()
+            .find(|mv| mv.is_castle() && mv.to() == Square::H1)
+            .unwrap();
+        // e1h1
+        assert_eq!(encode(&castle), 0x0107);
+    }
+}
@@ -1,38 +1,80 @@
 mod about;
 
-use crate::board::Board;
+use crate::board::{Board, Variant};
+use crate::book::Book;
+use crate::tablebase::Tablebases;
 use crate::transposition::TranspositionTable;
+use crate::types::parameters::Parameters;
+use crate::util::Rng;
 use crate::{
     benchmark::{benchmark, perft},
-    search::Search,
+    search::{Search, SearchReport, SearchResult},
     types::Score,
 };
 use std::io;
 use std::sync::{Arc, Mutex};
+use std::thread;
 use std::time::Instant;
 
-use shakmaty::Chess;
+use crossbeam_channel::{unbounded, Sender};
+use shakmaty::{CastlingMode, Chess, Move};
 
 pub struct Engine {
     board: Arc<Mutex<Board>>,
     search: Search,
-    tt_search: Arc<Mutex<TranspositionTable>>,
+    tt_search: Arc<TranspositionTable>,
+    params: Arc<Mutex<Parameters>>,
+    variant: Variant,
+    book: Option<Book>,
+    tablebase: Arc<Mutex<Option<Tablebases>>>,
+    rng: Rng,
+    /// Where the search thread sends its results, kept to restart the thread.
+    results: Option<Sender<SearchReport>>,
 }
 
 impl Engine {
     pub fn new() -> Self {
-        let tt_search: Arc<Mutex<TranspositionTable>> =
-            Arc::new(Mutex::new(TranspositionTable::default()));
         Engine {
             board: Arc::new(Mutex::new(Board::starting_position())),
             search: Search::new(),
-            tt_search,
+            tt_search: Arc::new(TranspositionTable::default()),
+            params: Arc::new(Mutex::new(Parameters::default())),
+            variant: Variant::default(),
+            book: None,
+            tablebase: Arc::new(Mutex::new(None)),
+            rng: Rng::from_time(),
+            results: None,
         }
     }
 
+    /// Starts the search thread, on the current transposition table.
+    fn start_search(&mut self) {
+        let Some(results) = self.results.clone() else {
+            return;
+        };
+        self.search.init(
+            Arc::clone(&self.board),
+            Arc::clone(&self.tt_search),
+            Arc::clone(&self.params),
+            Arc::clone(&self.tablebase),
+            results,
+        );
+    }
+
     pub fn run(&mut self) {
-        self.search
-            .init(Arc::clone(&self.board), Arc::clone(&self.tt_search));
+        // Everything the search reports comes back over a channel, and is printed as UCI here
+        let (results, receiver) = unbounded::<SearchReport>();
+        thread::spawn(move || {
+            for report in receiver {
+                match report {
+                    SearchReport::Info(line) => println!("{line}"),
+                    SearchReport::Done(result) => print_result(&result),
+                }
+            }
+        });
+
+        self.results = Some(results);
+        self.start_search();
         self.print_logo();
 
         let mut cmd = String::new();
@@ -44,75 +86,60 @@ impl Engine {
 
             cmd = cmd.trim_end().to_string();
 
-            if cmd == "quit" {
-                quit = true;
-                self.search.send("quit".to_string());
-            }
-            if cmd == "uci" {
-                self.print_options();
-                println!("uciok");
-            }
-            if cmd == "isready" {
-                println!("readyok");
-            }
-            if cmd == "ucinewgame" {
-                self.tt_search.lock().unwrap().clear();
-            }
-
-            if cmd.starts_with("position") {
-                enum Options {
-                    Nothing,
-                    Fen,
-                    Moves,
-                }
-                let mut fen_string = String::from("");
-                let mut moves: Vec<String> = Vec::new();
-                let mut skip_fen = false;
-                let mut option = Options::Nothing;
-                let tokens: Vec<&str> = cmd.split_whitespace().collect();
-                for token in tokens {
-                    match token {
-                        "position" => (),
-                        "startpos" => skip_fen = true,
-                        "fen" => option = Options::Fen,
-                        "moves" => option = Options::Moves,
-                        _ => match option {
-                            Options::Nothing => (),
-                            Options::Fen => {
-                                fen_string.push_str(token);
-                                fen_string.push(' ');
-                            }
-                            Options::Moves => moves.push(token.to_string()),
-                        },
-                    }
+            // Commands are routed on their first token, the rest is up to each command
+            let mut tokens = cmd.split_whitespace();
+            match tokens.next() {
+                Some("quit") => {
+                    quit = true;
+                    self.search.shutdown();
                 }
-
-                if skip_fen {
-                    fen_string =
-                        String::from("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+                Some("uci") => {
+                    self.print_options();
+                    println!("uciok");
                 }
-                let mut board_guard = self.board.lock().unwrap();
-                *board_guard = Board::new(&fen_string).unwrap();
-
-                for mv in moves {
-                    board_guard.play_uci(&mv);
+                Some("isready") => println!("readyok"),
+                Some("debug") => match tokens.next() {
+                    Some("on") => self.search.set_debug(true),
+                    Some("off") => self.search.set_debug(false),
+                    _ => (),
+                },
+                Some("ucinewgame") => {
+                    // The old game's search is pointless, and would refill the TT as it's cleared
+                    if !self.search.is_idle() {
+                        self.search.stop();
+                    }
+                    self.tt_search.clear();
+                    self.search.new_game();
                 }
-            }
-
-            if cmd.starts_with("go") {
-                if let Some(token) = cmd.split_whitespace().nth(1) {
-                    if token == "movetime" {
-                        self.search.send(cmd[3..].to_string());
+                Some("setoption") => self.set_option(&cmd),
+                Some("position") => self.position(tokens),
+                Some("go") => {
+                    // Normally a search that was just told to stop. It reports its bestmove
+                    // first, and clearing the stop flag for the new search can't revive it.
+                    if self.search.is_searching() {
+                        self.search.stop();
+                        self.search.wait();
+                    }
+                    // A bestmove can't be sent while pondering, so the search takes over there
+                    let ponder = tokens.any(|token| token == "ponder");
+                    let board_guard = self.board.lock().unwrap();
+                    if let Some(mv) = self
+                        .book
+                        .as_ref()
+                        .filter(|_| !ponder)
+                        .and_then(|book| book.probe(&board_guard, &mut self.rng))
+                    {
+                        println!("bestmove {}", board_guard.to_uci(&mv));
+                    } else {
+                        self.search.go(cmd.clone());
                     }
                 }
-                self.search.send("go".to_string());
-            }
-            if cmd == "stop" {
-                self.search.send("stop".to_string());
-            }
-            if cmd.starts_with("perft") {
-                if let Some(depth) = cmd.split_whitespace().nth(1) {
-                    if let Ok(depth) = depth.parse::<i32>() {
+                Some("stop") => self.search.stop(),
+                Some("ponderhit") => self.search.ponderhit(),
+                Some("stats") => self.search.send("stats".to_string()),
+                Some("moveorder") => self.search.send("moveorder".to_string()),
+                Some("perft") => {
+                    if let Some(Ok(depth)) = tokens.next().map(str::parse::<i32>) {
                         let now = Instant::now();
                         let nodes = perft(&Chess::default(), depth);
 
@@ -126,11 +153,8 @@ impl Engine {
                         )
                     }
                 }
-            }
-
-            if cmd.starts_with("bench") {
-                if let Some(depth) = cmd.split_whitespace().nth(1) {
-                    if let Ok(depth) = depth.parse::<i32>() {
+                Some("bench") => {
+                    if let Some(Ok(depth)) = tokens.next().map(str::parse::<i32>) {
                         let pos = Chess::new();
                         let mut nodes = 0;
                         let now = Instant::now();
@@ -146,13 +170,221 @@ impl Engine {
                         )
                     }
                 }
-            }
-            if cmd == "eval" {
-                let board_guard = self.board.lock().unwrap();
-                println!("{}", board_guard.evaluate());
+                Some("eval") => {
+                    let mut board_guard = self.board.lock().unwrap();
+                    println!("Piece contributions (White's perspective):");
+                    for (square, piece, value) in board_guard.piece_contributions() {
+                        println!("  {} {} {:+}", piece.char(), square, value);
+                    }
+                    println!(
+                        "Raw network output: {} ({} to move)",
+                        board_guard.raw_evaluate(),
+                        board_guard.turn().fold_wb("white", "black")
+                    );
+                    println!("Static evaluation: {}", board_guard.evaluate());
+                    for square in board_guard.board().occupied() {
+                        let color = board_guard.color_on(square).unwrap();
+                        let gain = board_guard.see_square(square, !color);
+                        if gain > 0 {
+                            println!("Hanging: {square} (loses {gain})");
+                        }
+                    }
+                    println!(
+                        "Material key: {:012x} ({} pieces)",
+                        board_guard.material_key(),
+                        board_guard.piece_count()
+                    );
+                }
+                Some("mirror") => {
+                    // Both evaluations from White's perspective, which should cancel out
+                    let mut board_guard = self.board.lock().unwrap();
+                    let mut mirrored = board_guard.mirror();
+                    let white = |board: &mut Board| board.turn().fold_wb(1, -1) * board.evaluate();
+                    let (original, flipped) = (white(&mut board_guard), white(&mut mirrored));
+                    println!("Original: {} ({})", original, board_guard.fen());
+                    println!("Mirrored: {} ({})", flipped, mirrored.fen());
+                    println!(
+                        "Symmetric: {}",
+                        if original == -flipped { "yes" } else { "no" }
+                    );
+                }
+                _ => (),
             }
 
             cmd = String::new();
         }
     }
+
+    /// Sets up the board from the tokens after `position`.
+    fn position<'a>(&mut self, tokens: impl Iterator<Item = &'a str>) {
+        enum Options {
+            Nothing,
+            Fen,
+            Moves,
+        }
+        let mut fen_string = String::from("");
+        let mut moves: Vec<String> = Vec::new();
+        let mut skip_fen = false;
+        let mut option = Options::Nothing;
+        for token in tokens {
+            match token {
+                "startpos" => skip_fen = true,
+                "fen" => option = Options::Fen,
+                "moves" => option = Options::Moves,
+                _ => match option {
+                    Options::Nothing => (),
+                    Options::Fen => {
+                        fen_string.push_str(token);
+                        fen_string.push(' ');
+                    }
+                    Options::Moves => moves.push(token.to_string()),
+                },
+            }
+        }
+
+        if skip_fen {
+            fen_string = String::from("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        }
+        let mut board_guard = self.board.lock().unwrap();
+        let set_up = if board_guard.variant() == self.variant {
+            board_guard.set_from_fen(&fen_string)
+        } else {
+            Board::with_variant(&fen_string, self.variant).map(|board| *board_guard = board)
+        };
+        // The moves belong to that position, so none of them are played on the previous one
+        if let Err(err) = set_up {
+            println!("info string {err}, keeping the previous position");
+            return;
+        }
+
+        // Stop at the first bad move rather than playing the rest on the wrong position
+        for mv in moves {
+            if let Err(err) = board_guard.try_play_uci(&mv) {
+                println!("info string {err}, ignoring the remaining moves");
+                break;
+            }
+        }
+    }
+
+    fn set_option(&mut self, cmd: &str) {
+        let tokens: Vec<&str> = cmd.split_whitespace().collect();
+        let name = tokens.iter().position(|&token| token == "name");
+        let value = tokens.iter().position(|&token| token == "value");
+        let (Some(name), Some(value)) = (name, value) else {
+            return;
+        };
+        if value <= name {
+            return;
+        }
+        let name = tokens[name + 1..value].join(" ");
+        let value = tokens[value + 1..].join(" ");
+
+        match name.as_str() {
+            "Hash" => {
+                // The search thread shares the table without a lock, so it's restarted on a
+                // table of the new size
+                if let Ok(megabytes) = value.parse::<usize>() {
+                    self.search.shutdown();
+                    self.tt_search = Arc::new(TranspositionTable::new(megabytes.clamp(1, 65536)));
+                    self.start_search();
+                }
+            }
+            "UCI_Variant" => {
+                if let Some(variant) = Variant::from_uci(&value) {
+                    self.variant = variant;
+                }
+            }
+            "BookFile" => {
+                self.book = match value.as_str() {
+                    "" | "<empty>" => None,
+                    path => match Book::load(path) {
+                        Ok(book) => Some(book),
+                        Err(err) => {
+                            println!("info string Failed to load book {path}: {err}");
+                            None
+                        }
+                    },
+                };
+            }
+            "UCI_ShowWDL" => {
+                if let Ok(show_wdl) = value.parse::<bool>() {
+                    self.search.set_show_wdl(show_wdl);
+                }
+            }
+            "UCI_ShowSAN" => {
+                if let Ok(show_san) = value.parse::<bool>() {
+                    self.search.set_show_san(show_san);
+                }
+            }
+            "DrawJitter" => {
+                if let Ok(draw_jitter) = value.parse::<bool>() {
+                    self.search.set_draw_jitter(draw_jitter);
+                }
+            }
+            "Seed" => {
+                // Zero leaves the choice to the clock
+                self.rng = match value.parse::<u64>() {
+                    Ok(0) | Err(_) => Rng::from_time(),
+                    Ok(seed) => Rng::new(seed),
+                };
+            }
+            "Move Overhead" => {
+                if let Ok(milliseconds) = value.parse::<u64>() {
+                    self.search.set_move_overhead(milliseconds.min(5000));
+                }
+            }
+            "SyzygyPath" => {
+                let tablebase = match value.as_str() {
+                    "" | "<empty>" => None,
+                    path => match Tablebases::load(path) {
+                        Ok(tablebase) => {
+                            println!(
+                                "info string Loaded tablebases up to {} pieces",
+                                tablebase.max_pieces()
+                            );
+                            Some(tablebase)
+                        }
+                        Err(err) => {
+                            println!("info string Failed to load tablebases {path}: {err}");
+                            None
+                        }
+                    },
+                };
+                *self.tablebase.lock().unwrap() = tablebase;
+            }
+            // Search tunables, applied from the next `go` onwards
+            _ => {
+                self.params.lock().unwrap().set(&name, &value);
+            }
+        }
+    }
+}
+
+/// Prints the outcome of a search as UCI: a closing info line, then `bestmove`.
+fn print_result(result: &SearchResult) {
+    let uci = |mv: &Move| mv.to_uci(CastlingMode::Standard);
+    // Repeats what the search settled on, since an interrupted iteration may have reported
+    // since. Nothing was settled before the first iteration completes.
+    if result.depth > 0 {
+        let mut line = format!(
+            "info depth {} score {} nodes {}",
+            result.depth,
+            Score::to_uci(result.score),
+            result.nodes
+        );
+        if !result.pv.is_empty() {
+            line.push_str(" pv");
+            for mv in &result.pv {
+                line.push_str(&format!(" {}", uci(mv)));
+            }
+        }
+        println!("{line}");
+    }
+    match (&result.best_move, result.pv.get(1)) {
+        (Some(mv), Some(ponder)) if result.pv.first() == Some(mv) => {
+            println!("bestmove {} ponder {}", uci(mv), uci(ponder))
+        }
+        (Some(mv), _) => println!("bestmove {}", uci(mv)),
+        (None, _) => println!("bestmove (none)"),
+    }
 }
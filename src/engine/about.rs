@@ -1,4 +1,6 @@
 use super::Engine;
+use crate::search::DEFAULT_MOVE_OVERHEAD;
+use crate::transposition::DEFAULT_TT_SIZE;
 
 impl Engine {
     pub fn print_logo(&self) {
@@ -11,9 +13,24 @@ impl Engine {
     }
 
     pub fn print_options(&self) {
-        println!("{:<10} {} {}", "Engine", "hivemind", "v1.0");
-        println!("{:<10} {}", "Author", "aminwoo");
+        println!("id name hivemind v1.0");
+        println!("id author aminwoo");
         println!();
+        println!("option name Hash type spin default {DEFAULT_TT_SIZE} min 1 max 65536");
+        println!("option name Threads type spin default 1 min 1 max 1");
+        println!("option name MultiPV type spin default 1 min 1 max 1");
+        println!("option name Ponder type check default false");
+        println!(
+            "option name Move Overhead type spin default {DEFAULT_MOVE_OVERHEAD} min 0 max 5000"
+        );
+        println!("option name BookFile type string default <empty>");
+        println!("option name SyzygyPath type string default <empty>");
+        println!("option name DrawJitter type check default true");
+        println!("option name Seed type spin default 0 min 0 max 2147483647");
+        println!("option name UCI_ShowWDL type check default false");
+        println!("option name UCI_ShowSAN type check default false");
         println!("option name UCI_Variant type combo default chess var chess var crazyhouse");
+        #[cfg(feature = "tuning")]
+        crate::types::parameters::print_options();
     }
 }
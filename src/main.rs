@@ -1,11 +1,14 @@
 mod benchmark;
 mod board;
+mod book;
 mod bughouse;
 mod engine;
 mod nnue;
 mod search;
+mod tablebase;
 mod transposition;
 mod types;
+mod util;
 
 use engine::Engine;
 
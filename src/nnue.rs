@@ -18,29 +18,89 @@ macro_rules! ft {
     };
 }
 
+/// Feature changes of a state that haven't been applied to its accumulators yet.
+#[derive(Clone, Default)]
+struct Update {
+    adds: Vec<FtIndex>,
+    subs: Vec<FtIndex>,
+}
+
+/// Accumulator stack of one board. The weights live in the `PARAMETERS` static and are shared by
+/// every board and thread, so a clone only copies the accumulators (about 150 KB).
 #[derive(Clone)]
 pub struct Network {
     index: usize,
     stack: Box<[[[i16; HIDDEN_SIZE]; 2]; MAX_PLY]>,
-    adds: Vec<FtIndex>,
-    subs: Vec<FtIndex>,
+    updates: Vec<Update>,
+    /// Whether the accumulators of each state are up to date. Pushed states are only computed
+    /// once they are evaluated, so nodes that never evaluate skip the update entirely.
+    computed: [bool; MAX_PLY],
 }
 
 impl Network {
-    /// Pushes the current state of accumulators onto the stack.
+    /// Pushes a new state on top of the current one. Its accumulators are computed lazily
+    /// from the parent state and the features changed since.
     pub fn push(&mut self) {
-        self.stack[self.index + 1] = self.stack[self.index];
+        assert!(
+            self.index + 1 < MAX_PLY,
+            "NNUE accumulator stack overflow at depth {}",
+            self.index
+        );
         self.index += 1;
+        self.computed[self.index] = false;
+        let update = &mut self.updates[self.index];
+        update.adds.clear();
+        update.subs.clear();
     }
 
     /// Pops the topmost state from the stack and restores the accumulators.
     pub fn pop(&mut self) {
+        debug_assert!(
+            self.index > 0,
+            "NNUE accumulator stack popped more than pushed"
+        );
         self.index -= 1;
     }
 
-    /// Computes the output score for the given color.
+    /// Drops every pushed state and empties the root accumulators, as `default` would
+    /// without allocating.
+    pub fn reset(&mut self) {
+        self.index = 0;
+        self.stack[0] = [PARAMETERS.input_bias.data; 2];
+        self.computed[0] = true;
+        let update = &mut self.updates[0];
+        update.adds.clear();
+        update.subs.clear();
+    }
+
+    /// Returns the number of states pushed on top of the root accumulators.
+    pub fn depth(&self) -> usize {
+        self.index
+    }
+
+    /// Returns the accumulators of the topmost state, which must have been computed.
+    pub fn accumulators(&self) -> &[[i16; HIDDEN_SIZE]; 2] {
+        debug_assert!(self.computed[self.index], "NNUE accumulators not computed");
+        &self.stack[self.index]
+    }
+
+    /// Brings the accumulators of the topmost state up to date, starting from the closest
+    /// computed state below it. The root state is always computed.
+    pub fn compute(&mut self) {
+        let mut base = self.index;
+        while !self.computed[base] {
+            base -= 1;
+        }
+        for level in base + 1..=self.index {
+            self.stack[level] = self.stack[level - 1];
+            self.apply(level);
+            self.computed[level] = true;
+        }
+    }
+
+    /// Computes the output score for the given color. The accumulators must have been computed.
     pub fn evaluate(&self, side_to_move: Color) -> i32 {
-        let accumulators = &self.stack[self.index];
+        let accumulators = self.accumulators();
 
         let stm = accumulators[!side_to_move as usize];
         let nstm = accumulators[side_to_move as usize];
@@ -52,67 +112,100 @@ impl Network {
             / (L0_SCALE * L1_SCALE)
     }
 
+    /// Applies the pending feature changes to the topmost state right away. Only for states
+    /// that are already computed, like the root when a move is played in place.
     pub fn commit(&mut self) {
-        match (&self.adds[..], &self.subs[..]) {
-            (&[add], &[]) => self.add1(add),
-            (&[add], &[sub]) => self.add1_sub1(add, sub),
-            (&[add], &[sub1, sub2]) => self.add1_sub2(add, sub1, sub2),
-            (&[add1, add2], &[sub1, sub2]) => self.add2_sub2(add1, add2, sub1, sub2),
-            (&[add1, add2], &[sub1, sub2, _]) => self.add2_sub2(add1, add2, sub1, sub2),
+        debug_assert!(self.computed[self.index], "NNUE commit onto a lazy state");
+        self.apply(self.index);
+        let update = &mut self.updates[self.index];
+        update.adds.clear();
+        update.subs.clear();
+    }
+
+    fn apply(&mut self, level: usize) {
+        let update = &self.updates[level];
+        match (&update.adds[..], &update.subs[..]) {
+            // Null moves change nothing
+            (&[], &[]) => (),
+            (&[add], &[]) => self.add1(level, add),
+            (&[add], &[sub]) => self.add1_sub1(level, add, sub),
+            (&[add], &[sub1, sub2]) => self.add1_sub2(level, add, sub1, sub2),
+            (&[add1, add2], &[sub1, sub2]) => self.add2_sub2(level, add1, add2, sub1, sub2),
+            (&[add1, add2], &[sub1, sub2, _]) => self.add2_sub2(level, add1, add2, sub1, sub2),
             _ => panic!(),
         }
-
-        self.adds.clear();
-        self.subs.clear();
     }
 
-    fn add1(&mut self, add: FtIndex) {
-        let accumulators = &mut self.stack[self.index];
-        for i in 0..HIDDEN_SIZE {
-            accumulators[0][i] += ft!(add.0, i);
-            accumulators[1][i] += ft!(add.1, i);
-        }
+    fn add1(&mut self, level: usize, add: FtIndex) {
+        add_feature(&mut self.stack[level], add);
     }
 
-    fn add1_sub1(&mut self, add: FtIndex, sub: FtIndex) {
-        let accumulators = &mut self.stack[self.index];
+    fn add1_sub1(&mut self, level: usize, add: FtIndex, sub: FtIndex) {
+        let accumulators = &mut self.stack[level];
         for i in 0..HIDDEN_SIZE {
             accumulators[0][i] += ft!(add.0, i) - ft!(sub.0, i);
             accumulators[1][i] += ft!(add.1, i) - ft!(sub.1, i);
         }
     }
 
-    fn add1_sub2(&mut self, add: FtIndex, sub1: FtIndex, sub2: FtIndex) {
-        let accumulators = &mut self.stack[self.index];
+    fn add1_sub2(&mut self, level: usize, add: FtIndex, sub1: FtIndex, sub2: FtIndex) {
+        let accumulators = &mut self.stack[level];
         for i in 0..HIDDEN_SIZE {
             accumulators[0][i] += ft!(add.0, i) - ft!(sub1.0, i) - ft!(sub2.0, i);
             accumulators[1][i] += ft!(add.1, i) - ft!(sub1.1, i) - ft!(sub2.1, i);
         }
     }
 
-    fn add2_sub2(&mut self, add1: FtIndex, add2: FtIndex, sub1: FtIndex, sub2: FtIndex) {
-        let accumulators = &mut self.stack[self.index];
+    fn add2_sub2(
+        &mut self,
+        level: usize,
+        add1: FtIndex,
+        add2: FtIndex,
+        sub1: FtIndex,
+        sub2: FtIndex,
+    ) {
+        let accumulators = &mut self.stack[level];
         for i in 0..HIDDEN_SIZE {
             accumulators[0][i] += ft!(add1.0, i) + ft!(add2.0, i) - ft!(sub1.0, i) - ft!(sub2.0, i);
             accumulators[1][i] += ft!(add1.1, i) + ft!(add2.1, i) - ft!(sub1.1, i) - ft!(sub2.1, i);
         }
     }
 
+    /// Adds a piece directly to the current accumulators, using the same perspective
+    /// layout as the incremental updates.
     pub fn accumulate(&mut self, color: Color, piece: Role, square: Square) {
-        let (black, white) = index(color, piece, square);
-        let accumulators = &mut self.stack[self.index];
-        for i in 0..HIDDEN_SIZE {
-            accumulators[0][i] += PARAMETERS.input_weights[white][i];
-            accumulators[1][i] += PARAMETERS.input_weights[black][i];
-        }
+        self.add1(self.index, index(color, piece, square));
     }
 
     pub fn activate(&mut self, color: Color, piece: Role, square: Square) {
-        self.adds.push(index(color, piece, square));
+        self.updates[self.index]
+            .adds
+            .push(index(color, piece, square));
     }
 
     pub fn deactivate(&mut self, color: Color, piece: Role, square: Square) {
-        self.subs.push(index(color, piece, square));
+        self.updates[self.index]
+            .subs
+            .push(index(color, piece, square));
+    }
+}
+
+/// Accumulators of the given pieces built from scratch, without a network around them. What
+/// the incremental updates are checked against in debug builds.
+pub fn refreshed_accumulators(
+    pieces: impl IntoIterator<Item = (Color, Role, Square)>,
+) -> [[i16; HIDDEN_SIZE]; 2] {
+    let mut accumulators = [PARAMETERS.input_bias.data; 2];
+    for (color, piece, square) in pieces {
+        add_feature(&mut accumulators, index(color, piece, square));
+    }
+    accumulators
+}
+
+fn add_feature(accumulators: &mut [[i16; HIDDEN_SIZE]; 2], add: FtIndex) {
+    for i in 0..HIDDEN_SIZE {
+        accumulators[0][i] += ft!(add.0, i);
+        accumulators[1][i] += ft!(add.1, i);
     }
 }
 
@@ -128,8 +221,8 @@ impl Default for Network {
         Self {
             index: 0,
             stack: Box::new([[PARAMETERS.input_bias.data; 2]; MAX_PLY]),
-            adds: Vec::default(),
-            subs: Vec::default(),
+            updates: vec![Update::default(); MAX_PLY],
+            computed: [true; MAX_PLY],
         }
     }
 }
@@ -142,6 +235,7 @@ struct Parameters {
     output_bias: AlignedBlock<i16>,
 }
 
+/// Embedded at compile time and never copied.
 static PARAMETERS: Parameters = unsafe { std::mem::transmute(*include_bytes!(env!("MODEL"))) };
 
 #[repr(align(64))]
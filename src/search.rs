@@ -7,82 +7,165 @@ mod qsearch;
 mod sorting;
 
 use crate::board::Board;
+use crate::tablebase::Tablebases;
 use crate::transposition::TranspositionTable;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle};
+use std::time::Duration;
 
 use crossbeam_channel::unbounded;
 use crossbeam_channel::Sender;
 
 use crate::types::parameters::Parameters;
-use defs::{SearchInfo, SearchParams, SearchRefs};
+use defs::{SearchInfo, SearchParams, SearchRefs, SearchStats};
+pub use defs::{SearchReport, SearchResult};
 use shakmaty::zobrist::Zobrist64;
 use shakmaty::Move;
+pub use sorting::see_square;
 
 pub struct Search {
     handle: Option<JoinHandle<()>>,
     sender: Option<Sender<String>>,
+    stop: Arc<AtomicBool>,
+    ponderhit: Arc<AtomicBool>,
+    searching: Arc<AtomicBool>,
+    show_wdl: Arc<AtomicBool>,
+    show_san: Arc<AtomicBool>,
+    debug: Arc<AtomicBool>,
+    draw_jitter: Arc<AtomicBool>,
+    move_overhead: Arc<AtomicU64>,
 }
 
+/// Milliseconds reserved per move for GUI and network latency.
+pub const DEFAULT_MOVE_OVERHEAD: u64 = 10;
+
 impl Search {
     pub fn new() -> Self {
         Self {
             handle: None,
             sender: None,
+            stop: Arc::new(AtomicBool::new(false)),
+            ponderhit: Arc::new(AtomicBool::new(false)),
+            searching: Arc::new(AtomicBool::new(false)),
+            show_wdl: Arc::new(AtomicBool::new(false)),
+            show_san: Arc::new(AtomicBool::new(false)),
+            debug: Arc::new(AtomicBool::new(false)),
+            draw_jitter: Arc::new(AtomicBool::new(true)),
+            move_overhead: Arc::new(AtomicU64::new(DEFAULT_MOVE_OVERHEAD)),
         }
     }
 
-    pub fn init(&mut self, mtx_board: Arc<Mutex<Board>>, mtx_tt: Arc<Mutex<TranspositionTable>>) {
+    pub fn init(
+        &mut self,
+        mtx_board: Arc<Mutex<Board>>,
+        tt: Arc<TranspositionTable>,
+        mtx_params: Arc<Mutex<Parameters>>,
+        mtx_tablebase: Arc<Mutex<Option<Tablebases>>>,
+        results: Sender<SearchReport>,
+    ) {
         let (s, r) = unbounded::<String>();
+        let stop = Arc::clone(&self.stop);
+        let ponderhit = Arc::clone(&self.ponderhit);
+        let show_wdl = Arc::clone(&self.show_wdl);
+        let show_san = Arc::clone(&self.show_san);
+        let debug = Arc::clone(&self.debug);
+        let draw_jitter = Arc::clone(&self.draw_jitter);
+        let move_overhead = Arc::clone(&self.move_overhead);
+        let searching = Arc::clone(&self.searching);
         let h = thread::spawn(move || {
-            let mut search_params = SearchParams {
-                depth: 69,
-                search_time: 600000,
-            };
-            let mut quit = false;
-            let mut halt = true;
-
-            while !quit {
-                let cmd = r.recv().unwrap();
-                let board = mtx_board.lock().unwrap();
-                let mut tt = mtx_tt.lock().unwrap();
-
-                if cmd.starts_with("movetime") {
-                    if let Some(time_left) = cmd.split_whitespace().nth(1) {
-                        if let Ok(time_left) = time_left.parse::<u128>() {
-                            search_params.search_time = time_left;
+            let mut last_search = (SearchStats::default(), 0);
+            // Move ordering heuristics live on from one `go` to the next, until a new game
+            let mut search_info = SearchInfo::new();
+
+            // Idle until a command arrives. `go` searches until the search finishes or is
+            // stopped through the stop flag, then the thread is idle again.
+            while let Ok(cmd) = r.recv() {
+                match cmd.split_whitespace().next() {
+                    Some("go") => (),
+                    Some("quit") => break,
+                    Some("ucinewgame") => {
+                        // Only what was learnt is forgotten, the node counter stays shared
+                        let shared_nodes = search_info.shared_nodes;
+                        search_info = SearchInfo::new();
+                        search_info.shared_nodes = shared_nodes;
+                        continue;
+                    }
+                    Some("stats") => {
+                        for line in last_search.0.lines(last_search.1) {
+                            let _ = results.send(SearchReport::Info(line));
                         }
+                        continue;
                     }
+                    Some("moveorder") => {
+                        // Ordered with what the searches so far have learnt
+                        let mut board = mtx_board.lock().unwrap().clone();
+                        let mut search_params = SearchParams::default();
+                        let refs = SearchRefs {
+                            board: &mut board,
+                            params: mtx_params.lock().unwrap().clone(),
+                            search_params: &mut search_params,
+                            search_info: &mut search_info,
+                            tt: &tt,
+                            tt_enabled: true,
+                            tablebase: None,
+                            stop: &stop,
+                            ponderhit: &ponderhit,
+                            reports: Some(&results),
+                        };
+                        for line in Search::move_order(&refs) {
+                            refs.report(line);
+                        }
+                        continue;
+                    }
+                    _ => continue,
                 }
 
-                match cmd.as_str() {
-                    "go" => halt = false,
-                    "stop" => halt = true,
-                    "quit" => quit = true,
-                    _ => (),
-                }
+                // The search works on its own copy, so the board is free again right away.
+                // The TT needs no lock at all. Tablebases stay locked until the search is
+                // done, which UCI allows since the GUI may not change them in the meantime.
+                let mut board = mtx_board.lock().unwrap().clone();
 
-                if !halt && !quit {
-                    let mut search_info = SearchInfo::new();
-
-                    let mut search_refs = SearchRefs {
-                        board: &mut board.clone(),
-                        params: Parameters::default(),
-                        search_params: &mut search_params,
-                        search_info: &mut search_info,
-                        tt: &mut tt,
-                        tt_enabled: true,
-                    };
-
-                    let best_move = Search::iterative_deepening(&mut search_refs);
-                    if let Some(mv) = best_move {
-                        println!("bestmove {}", search_refs.board.to_uci(&mv));
-                    } else {
-                        println!("bestmove (none)");
-                    }
+                let mut search_params = SearchParams::from_go(&cmd);
+                search_params.allocate_time(board.turn());
+                search_params.apply_overhead(move_overhead.load(Ordering::Relaxed).into());
+                search_params.show_wdl = show_wdl.load(Ordering::Relaxed);
+                search_params.show_san = show_san.load(Ordering::Relaxed);
+                search_params.debug = debug.load(Ordering::Relaxed);
+                // Fixed-node searches are for reproducible tests, which want exact draw scores
+                search_params.draw_jitter =
+                    search_params.nodes.is_none() && draw_jitter.load(Ordering::Relaxed);
+                let tablebase = mtx_tablebase.lock().unwrap();
+                search_info = search_info.next_search();
+
+                let mut search_refs = SearchRefs {
+                    board: &mut board,
+                    params: mtx_params.lock().unwrap().clone(),
+                    search_params: &mut search_params,
+                    search_info: &mut search_info,
+                    tt: &tt,
+                    tt_enabled: true,
+                    tablebase: tablebase.as_ref(),
+                    stop: &stop,
+                    ponderhit: &ponderhit,
+                    reports: Some(&results),
+                };
 
-                    halt = true;
+                let result = Search::iterative_deepening(&mut search_refs);
+                search_info.flush_nodes();
+                // UCI forbids a bestmove while pondering, even if the search finished early
+                while search_params.ponder
+                    && !stop.load(Ordering::Relaxed)
+                    && !ponderhit.load(Ordering::Relaxed)
+                {
+                    thread::sleep(Duration::from_millis(1));
                 }
+                last_search = (search_info.stats.clone(), search_info.total_nodes());
+                // Idle before the result goes out, so that a `go` sent in reply to it
+                // never sees a search that is still running
+                searching.store(false, Ordering::Release);
+                // Nobody listening is not an error for the search itself
+                let _ = results.send(SearchReport::Done(result));
             }
         });
 
@@ -95,4 +178,124 @@ impl Search {
             s.send(cmd).expect("Broken channel");
         }
     }
+
+    /// Forgets what earlier searches learnt, once the search running now has finished.
+    pub fn new_game(&mut self) {
+        self.send("ucinewgame".to_string());
+    }
+
+    /// Starts a search. The stop flag is cleared here rather than on the search thread,
+    /// so that a `stop` sent right after `go` can't be lost.
+    pub fn go(&mut self, cmd: String) {
+        self.stop.store(false, Ordering::Relaxed);
+        self.ponderhit.store(false, Ordering::Relaxed);
+        self.searching.store(true, Ordering::Release);
+        self.send(cmd);
+    }
+
+    /// Whether a search is running, from `go` until its result has been handed over.
+    pub fn is_searching(&self) -> bool {
+        self.searching.load(Ordering::Acquire)
+    }
+
+    pub fn is_idle(&self) -> bool {
+        !self.is_searching()
+    }
+
+    /// Blocks until the running search, if any, has handed over its result.
+    pub fn wait(&self) {
+        while self.is_searching() {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// The opponent played the expected move, so the ponder search continues on our own clock.
+    pub fn ponderhit(&mut self) {
+        self.ponderhit.store(true, Ordering::Relaxed);
+    }
+
+    /// Interrupts the running search, which then reports its best move so far.
+    pub fn stop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    pub fn set_move_overhead(&mut self, milliseconds: u64) {
+        self.move_overhead.store(milliseconds, Ordering::Relaxed);
+    }
+
+    pub fn set_show_wdl(&mut self, show_wdl: bool) {
+        self.show_wdl.store(show_wdl, Ordering::Relaxed);
+    }
+
+    pub fn set_show_san(&mut self, show_san: bool) {
+        self.show_san.store(show_san, Ordering::Relaxed);
+    }
+
+    pub fn set_debug(&mut self, debug: bool) {
+        self.debug.store(debug, Ordering::Relaxed);
+    }
+
+    pub fn set_draw_jitter(&mut self, draw_jitter: bool) {
+        self.draw_jitter.store(draw_jitter, Ordering::Relaxed);
+    }
+
+    /// Stops any running search and waits for the search thread to exit.
+    pub fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.send("quit".to_string());
+        self.sender = None;
+        if let Some(handle) = self.handle.take() {
+            handle.join().expect("Search thread panicked");
+        }
+    }
+}
+
+impl Drop for Search {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Search, SearchReport};
+    use crate::board::Board;
+    use crate::transposition::TranspositionTable;
+    use crate::types::parameters::Parameters;
+    use crossbeam_channel::unbounded;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_is_searching() {
+        let mut search = Search::new();
+        let (results, receiver) = unbounded::<SearchReport>();
+        search.init(
+            Arc::new(Mutex::new(Board::starting_position())),
+            Arc::new(TranspositionTable::new(1)),
+            Arc::new(Mutex::new(Parameters::default())),
+            Arc::new(Mutex::new(None)),
+            results,
+        );
+        assert!(search.is_idle());
+
+        search.go("go movetime 20".to_string());
+        assert!(search.is_searching());
+        // Progress arrives over the channel too, ahead of the result
+        let mut infos = Vec::new();
+        let result = loop {
+            match receiver.recv().unwrap() {
+                SearchReport::Info(line) => infos.push(line),
+                SearchReport::Done(result) => break result,
+            }
+        };
+        assert!(result.best_move.is_some());
+        assert!(search.is_idle());
+        assert!(infos.iter().any(|line| line.starts_with("info depth 1 ")));
+
+        search.send("stats".to_string());
+        match receiver.recv().unwrap() {
+            SearchReport::Info(line) => assert!(line.starts_with("info string")),
+            SearchReport::Done(_) => panic!("stats don't end a search"),
+        }
+    }
 }
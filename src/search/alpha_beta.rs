@@ -1,26 +1,38 @@
 use crate::search::sorting::see;
+use crate::tablebase;
 use crate::transposition::{Bound, Entry};
-use crate::types::{parameters::*, Score, MAX_PLY};
+use crate::types::parameters::{Parameters, LMP_DEPTH, LMP_MARGIN};
+use crate::types::{Score, MAX_EXTENSIONS, MAX_PLY};
 
-use super::{defs::SearchRefs, Search};
-use shakmaty::{Move, MoveList};
+use super::{
+    defs::{SearchRefs, INFO_INTERVAL},
+    Search,
+};
+use shakmaty::{Color, Move, MoveList, Rank, Role};
+
+/// Halfmove clock from which TT cutoffs are no longer trusted.
+const TT_CUTOFF_HALFMOVES: u32 = 90;
 
 impl Search {
     pub fn alpha_beta(refs: &mut SearchRefs, mut depth: i32, mut alpha: i32, mut beta: i32) -> i32 {
         let ply = refs.board.ply();
+        // Nodes that return early end the line at their parent's move
         refs.search_info.pv[ply].fill(None);
+        refs.search_info.pv_length[ply] = ply;
 
-        if (refs.search_info.nodes & 2047) == 0
-            && refs.search_info.elapsed() > refs.search_params.search_time
-        {
-            refs.search_info.terminated = true;
+        if refs.check_time() {
             return 0;
         }
 
         let is_root = ply == 0;
+        if is_root {
+            refs.search_info.best_root_move = None;
+        }
         let pv_node = beta - alpha > 1;
         let original_alpha = alpha;
         let in_check = refs.board.in_check();
+        let excluded = refs.search_info.excluded[ply].clone();
+        let tt_key = refs.board.hash_excluding(&excluded);
 
         let mut best_score: i32 = -Score::INFINITY;
         let mut best_move: Option<&Move> = None;
@@ -29,9 +41,13 @@ impl Search {
         let mut quiets = MoveList::default();
 
         if !is_root {
-            // Draw Detection
-            if refs.board.three_fold() {
-                return Score::DRAW;
+            // Draw Detection. A mate delivered on the hundredth halfmove still counts, so the
+            // fifty-move rule is only applied when not in check.
+            if refs.board.three_fold()
+                || refs.board.is_insufficient_material()
+                || (refs.board.halfmoves() >= 100 && !in_check)
+            {
+                return refs.draw_score();
             }
 
             // Mate Distance Pruning
@@ -52,125 +68,265 @@ impl Search {
         depth = depth.max(0);
 
         let mut tt_move: Option<Move> = None;
-        let hit = refs.tt.read(refs.board.get_hash(), ply);
+        let hit = refs.probe_tt(tt_key);
+        refs.search_info.stats.tt_probes += 1;
         if let Some(hit) = &hit {
-            if !pv_node && hit.valid_cutoff(alpha, beta, depth) {
+            refs.search_info.stats.tt_hits += 1;
+            // Stored scores don't know how the node was reached, so don't trust them when
+            // a repetition or the fifty-move rule could turn the line into a draw
+            if !pv_node
+                && hit.valid_cutoff(alpha, beta, depth)
+                && refs.board.halfmoves() < TT_CUTOFF_HALFMOVES
+                && !refs.board.has_recent_repetition()
+            {
                 return hit.score;
             }
             tt_move = hit.mv.clone();
         }
 
+        // Tablebase Probing. The result is exact, so the subtree doesn't need to be searched.
+        if !is_root && excluded.is_none() {
+            if let Some(wdl) = refs.tablebase.and_then(|tb| tb.probe_wdl(refs.board)) {
+                refs.search_info.stats.tb_hits += 1;
+                return tablebase::score(wdl, ply);
+            }
+        }
+
         // Internal Iterative Reductions
-        if !is_root && tt_move.is_none() && depth >= iir_depth() {
+        if !is_root && tt_move.is_none() && depth >= refs.params.iir_depth() {
             depth -= 1;
         }
 
+        // Checks are extended by the parent. This only matters for nodes that reach a check
+        // without one, like the root, which still need a ply to find the evasions.
         if in_check {
-            depth += 1;
+            depth = depth.max(1);
         }
 
         refs.search_info.nodes += 1;
         refs.search_info.sel_depth = refs.search_info.sel_depth.max(ply);
-        refs.search_info.pv_length[ply] = ply;
 
         let eval = refs.board.evaluate();
         let improving = refs.board.is_improving();
         refs.board
             .set_eval(ply, if in_check { -Score::INFINITY } else { eval });
 
-        if !in_check && !pv_node && !is_root {
+        if !in_check && !pv_node && !is_root && excluded.is_none() {
             // Reverse Futility Pruning
-            if depth < rfp_depth() && eval - rfp_margin() * (depth - i32::from(improving)) > beta {
+            if refs.params.use_futility()
+                && depth < refs.params.rfp_depth()
+                && Score::offset(
+                    eval,
+                    -Score::margin(refs.params.rfp_margin(), depth - i32::from(improving)),
+                ) > beta
+            {
                 return eval;
             }
             // Razoring
-            if depth <= razoring_depth()
-                && eval + razoring_margin() * depth + razoring_fixed_margin() <= alpha
+            if depth <= refs.params.razoring_depth()
+                && Score::offset(
+                    eval,
+                    Score::margin(refs.params.razoring_margin(), depth)
+                        .saturating_add(refs.params.razoring_fixed_margin()),
+                ) <= alpha
             {
+                // Only a clear fail low is trusted. A close one gets the full search, which can
+                // still find the quiet tactics quiescence doesn't look at.
                 let score = Search::qsearch(refs, alpha, beta);
-                if score <= alpha {
+                if score.saturating_add(refs.params.razoring_verify_margin()) <= alpha {
                     return score;
                 }
             }
             // Null move pruning
-            if !refs.board.is_last_move_null()
-                && depth >= 4
+            if refs.params.use_null_move()
+                && !refs.board.is_last_move_null()
+                && depth >= refs.params.nmp_depth()
                 && eval > beta
-                && refs.board.has_non_pawn_material()
+                && refs.board.non_pawn_material(refs.board.turn()) > 0
             {
-                let r = 3 + depth / 3 + ((eval - beta) / 200).min(4);
+                let r = null_move_reduction(&refs.params, depth, eval, beta);
 
                 refs.board.make_null_move();
                 let score = -Search::alpha_beta(refs, depth - r, -beta, -beta + 1);
                 refs.board.undo_null_move();
 
                 if score >= beta {
+                    refs.search_info.stats.null_move_cutoffs += 1;
                     return beta;
                 }
             }
         }
 
+        // Moves are generated once per node and borrowed by both the move loop and the mate and
+        // stalemate check below. Caching lists on the board wouldn't pay off: handing out a cached
+        // list means cloning it, which costs about half of generating it (~100ns against ~200ns
+        // on kiwipete).
         let mut moves = refs.board.legal_moves();
         Search::sort_moves(&mut moves, &refs.search_info.pv[ply][ply], &tt_move, refs);
 
-        for (moves_searched, mv) in (&moves).into_iter().enumerate() {
-            if !is_root && moves_searched > 0 && alpha > -Score::MATE_BOUND {
-                // Futility Pruning
-                if !pv_node
+        // Multi-Cut. If enough of the first few moves already fail high in a reduced search,
+        // the node is very likely a cut node and one of them would refute it at full depth.
+        if !pv_node
+            && !in_check
+            && !is_root
+            && excluded.is_none()
+            && depth >= refs.params.multicut_depth()
+        {
+            let reduced_depth = depth - 1 - refs.params.multicut_reduction();
+            let mut cutoffs = 0;
+            for mv in moves.iter().take(refs.params.multicut_moves() as usize) {
+                refs.search_info.extensions[ply + 1] = refs.search_info.extensions[ply];
+                refs.board.make_move::<false>(mv);
+                let score = -Search::alpha_beta(refs, reduced_depth, -beta, -beta + 1);
+                refs.board.undo_move();
+
+                if refs.search_info.terminated {
+                    return 0;
+                }
+                if score >= beta {
+                    cutoffs += 1;
+                    if cutoffs >= refs.params.multicut_cutoffs() {
+                        refs.search_info.stats.multicut_cutoffs += 1;
+                        return beta;
+                    }
+                }
+            }
+        }
+
+        // Set by late move pruning. Remaining quiets are passed over without any work, while
+        // captures sorted behind them still get searched.
+        let mut skip_quiets = false;
+        let endgame = refs.board.non_pawn_material(Color::White)
+            + refs.board.non_pawn_material(Color::Black)
+            < refs.params.pawn_push_material();
+
+        let searched = (&moves)
+            .into_iter()
+            .filter(|mv| excluded.as_ref() != Some(*mv));
+        for (moves_searched, mv) in searched.enumerate() {
+            if skip_quiets && !refs.board.is_noisy(mv) {
+                continue;
+            }
+            // Checks and promotions are where frontier nodes hide tactics the evaluation
+            // can't see, so neither futility nor SEE pruning touches them
+            let gives_check = refs.board.gives_check(mv);
+
+            if !is_root && moves_searched > 0 && !Score::is_loss(alpha) {
+                // Futility Pruning. Quiets with good history get a larger margin.
+                let history = refs
+                    .search_info
+                    .history
+                    .get_main(refs.board.turn(), mv)
+                    .unwrap_or(0);
+                if refs.params.use_futility()
+                    && !pv_node
                     && !in_check
-                    && !mv.is_capture()
-                    && depth <= fp_depth()
-                    && eval + fp_margin() * depth + fp_fixed_margin() < alpha
+                    && !refs.board.is_noisy(mv)
+                    && !gives_check
+                    && depth <= refs.params.fp_depth()
+                    && Score::offset(
+                        eval,
+                        Score::margin(refs.params.fp_margin(), depth)
+                            .saturating_add(refs.params.fp_fixed_margin())
+                            .saturating_add(history / refs.params.fp_history()),
+                    ) < alpha
                 {
-                    break;
+                    // Not a break: checks with worse history than this move are sorted behind it
+                    continue;
                 }
-                // Late Move Pruning. Leave the node after trying enough quiet moves with no success.
-                if !mv.is_capture()
+                // Late Move Pruning. Stop trying quiets after enough of them failed.
+                if !refs.board.is_noisy(mv)
                     && depth <= LMP_DEPTH
                     && quiets.len() as i32 > LMP_MARGIN + depth * depth / (2 - improving as i32)
                 {
-                    break;
-                } // Late Move Pruning. Leave the node after trying enough quiet moves with no success.
-                if !mv.is_capture()
-                    && depth <= LMP_DEPTH
-                    && quiets.len() as i32 > LMP_MARGIN + depth * depth
-                {
-                    break;
+                    skip_quiets = true;
+                    continue;
                 }
 
                 // Static Exchange Evaluation Pruning. Skip moves that are losing material.
-                if depth < see_depth()
-                    && !see(
-                        &refs.board.state(),
-                        mv,
-                        -[see_quiet_margin(), see_noisy_margin()][mv.is_capture() as usize] * depth,
-                    )
-                    .expect("Error evaluationg SEE")
+                // Captures with good history are allowed to lose a little more.
+                if refs.params.use_see_pruning()
+                    && depth < refs.params.see_depth()
+                    && !gives_check
+                    && !mv.is_promotion()
                 {
-                    continue;
+                    let threshold = if refs.board.is_noisy(mv) {
+                        let history = refs
+                            .search_info
+                            .history
+                            .get_capture(refs.board.turn(), mv)
+                            .unwrap_or(0);
+                        noisy_see_threshold(
+                            refs.params.see_noisy_margin() * depth,
+                            history / refs.params.see_history(),
+                        )
+                    } else {
+                        -refs.params.see_quiet_margin() * depth
+                    };
+                    if !see(refs.board.board(), refs.board.turn(), mv, threshold)
+                        .expect("Error evaluationg SEE")
+                    {
+                        continue;
+                    }
                 }
             }
+            // The move won't be pruned, so start loading its entry while the rest is decided
+            refs.tt.prefetch(refs.board.hash_after(mv));
+
+            if is_root && refs.search_info.elapsed() > INFO_INTERVAL {
+                refs.report(Search::format_currmove(refs, depth, mv, moves_searched + 1));
+            }
+
+            // Recapture Extension. Extend recaptures on PV nodes.
+            let recapture = pv_node
+                && mv.is_capture()
+                && refs
+                    .board
+                    .last_move()
+                    .is_some_and(|prev| prev.is_capture() && prev.to() == mv.to());
+            // Pawn Push Extension. In endgames, a pawn reaching the seventh rank starts a
+            // promotion race that a reduced search easily misjudges.
+            let pawn_push = endgame && is_push_to_seventh(mv, refs.board.turn());
+            // Check Extension. Decided before the move is made. All extensions share the line's
+            // budget, which bounds the checks that are never reduced.
+            let extension = i32::from(
+                (recapture || pawn_push || gives_check)
+                    && refs.search_info.extensions[ply] < MAX_EXTENSIONS,
+            );
+            refs.search_info.extensions[ply + 1] = refs.search_info.extensions[ply] + extension;
+            // Quiet checks that just give the piece away are common, and searching all of them
+            // unreduced costs more than it finds
+            let unreduced = extension > 0
+                && (recapture
+                    || pawn_push
+                    || see(refs.board.board(), refs.board.turn(), mv, 0)
+                        .expect("Error evaluationg SEE"));
+
             refs.board.make_move::<false>(mv);
-            refs.tt.prefetch(refs.board.get_hash());
 
             let mut score;
             if moves_searched == 0 {
-                score = -Search::alpha_beta(refs, depth - 1, -beta, -alpha);
+                score = -Search::alpha_beta(refs, depth - 1 + extension, -beta, -alpha);
             } else {
-                let reduction = Search::calculate_reduction(
-                    refs,
-                    pv_node,
-                    mv,
-                    depth,
-                    moves_searched as i32,
-                    improving,
-                    &hit,
-                );
-
-                let mut new_depth = depth - 1;
+                // Never reduce a move that is being extended, unless it's a check losing material
+                let reduction = if unreduced || !refs.params.use_lmr() {
+                    0
+                } else {
+                    Search::calculate_reduction(
+                        refs,
+                        pv_node,
+                        mv,
+                        depth,
+                        moves_searched as i32,
+                        improving,
+                        &hit,
+                    )
+                };
+
+                let mut new_depth = depth - 1 + extension;
                 score = -Search::alpha_beta(refs, new_depth - reduction, -alpha - 1, -alpha);
                 if alpha < score && reduction > 0 {
-                    new_depth += i32::from(score > best_score + search_deeper_margin());
+                    new_depth += i32::from(score > best_score + refs.params.search_deeper_margin());
                     score = -Search::alpha_beta(refs, new_depth, -alpha - 1, -alpha);
                 }
                 if alpha < score && score < beta {
@@ -180,9 +336,19 @@ impl Search {
 
             refs.board.undo_move();
 
+            if refs.search_info.terminated {
+                return 0;
+            }
+            if is_root {
+                refs.search_info.root_move_searched = true;
+            }
+
             if score > best_score {
                 best_score = score;
                 best_move = Some(mv);
+                if is_root {
+                    refs.search_info.best_root_move = Some(mv.clone());
+                }
 
                 if score > alpha {
                     alpha = score;
@@ -191,10 +357,14 @@ impl Search {
             }
 
             if alpha >= beta {
+                refs.search_info.stats.beta_cutoffs += 1;
+                if moves_searched == 0 {
+                    refs.search_info.stats.first_move_cutoffs += 1;
+                }
                 break;
             }
 
-            if mv.is_capture() {
+            if refs.board.is_noisy(mv) {
                 captures.push(mv.clone());
             } else {
                 quiets.push(mv.clone());
@@ -208,6 +378,10 @@ impl Search {
                 Score::DRAW
             };
         }
+        // The excluded move was the only one, so the node says nothing about the alternatives
+        if best_move.is_none() && excluded.is_some() {
+            return alpha;
+        }
 
         let bound = match best_score {
             s if s <= original_alpha => Bound::Alpha,
@@ -224,14 +398,8 @@ impl Search {
             );
         }
 
-        refs.tt.write(
-            refs.board.get_hash(),
-            depth,
-            best_score,
-            bound,
-            best_move.cloned(),
-            ply,
-        );
+        refs.tt
+            .write(tt_key, depth, best_score, bound, best_move.cloned(), ply);
         best_score
     }
 
@@ -242,21 +410,30 @@ impl Search {
         captures: MoveList,
         quiets: MoveList,
     ) {
-        if best_move.is_capture() {
+        if refs.board.is_noisy(&best_move) {
             refs.search_info.history.update_capture(
-                refs.board.state(),
+                &refs.params,
+                refs.board.turn(),
                 &best_move,
                 &captures,
                 depth,
             );
         } else {
             refs.search_info.killers[refs.board.ply()] = Some(best_move.clone());
-            refs.search_info
-                .history
-                .update_main(refs.board.turn(), &best_move, &quiets, depth);
-            refs.search_info
-                .history
-                .update_continuation(refs.board, &best_move, &quiets, depth);
+            refs.search_info.history.update_main(
+                &refs.params,
+                refs.board.turn(),
+                &best_move,
+                &quiets,
+                depth,
+            );
+            refs.search_info.history.update_continuation(
+                &refs.params,
+                refs.board,
+                &best_move,
+                &quiets,
+                depth,
+            );
         }
     }
 
@@ -281,7 +458,10 @@ impl Search {
             i32::from(v) as f64
         }
 
-        if mv.is_capture() || moves < LMR_MOVES_PLAYED || depth < LMR_DEPTH {
+        if refs.board.is_noisy(mv)
+            || moves < refs.params.lmr_moves_played()
+            || depth < refs.params.lmr_depth()
+        {
             return 0;
         }
 
@@ -293,7 +473,7 @@ impl Search {
             .history
             .get_main(!refs.board.turn(), mv)
             .unwrap() as f64
-            / lmr_history() as f64;
+            / refs.params.lmr_history() as f64;
 
         reduction -= 0.88 * to_f64(!pv_node);
         reduction -= 0.78 * to_f64(refs.board.in_check());
@@ -302,7 +482,7 @@ impl Search {
             * to_f64(
                 entry
                     .as_ref()
-                    .is_some_and(|e| e.mv.is_some() && e.mv.clone().unwrap().is_capture()),
+                    .is_some_and(|e| e.mv.as_ref().is_some_and(|mv| refs.board.is_noisy(mv))),
             );
         reduction += 0.48 * to_f64(improving);
 
@@ -310,3 +490,391 @@ impl Search {
         (reduction as i32).clamp(0, depth)
     }
 }
+
+/// Null move reduction, deeper at higher depths and the further the evaluation is above beta.
+/// It's at least a ply, and at most the whole depth, which leaves only quiescence.
+fn null_move_reduction(params: &Parameters, depth: i32, eval: i32, beta: i32) -> i32 {
+    let eval_bonus =
+        (eval.saturating_sub(beta) / params.nmp_eval_divisor()).clamp(0, params.nmp_eval_max());
+    (params.nmp_base() + depth / params.nmp_depth_divisor() + eval_bonus).clamp(1, depth.max(1))
+}
+
+/// A pawn moving up to the rank before promotion, where nothing can stand in front of it.
+fn is_push_to_seventh(mv: &Move, turn: Color) -> bool {
+    matches!(mv, Move::Normal { role: Role::Pawn, to, .. }
+        if to.rank() == turn.fold_wb(Rank::Seventh, Rank::Second))
+}
+
+/// SEE threshold for a noisy move, lowered by its capture history. Bad history raises it, but
+/// never above zero, so that a capture losing nothing is never pruned.
+fn noisy_see_threshold(margin: i32, history_bonus: i32) -> i32 {
+    margin.saturating_neg().saturating_sub(history_bonus).min(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{noisy_see_threshold, null_move_reduction};
+    use crate::board::Board;
+    use crate::search::defs::{SearchInfo, SearchParams, SearchRefs};
+    use crate::search::Search;
+    use crate::transposition::{Bound, TranspositionTable};
+    use crate::types::parameters::Parameters;
+    use crate::types::{Score, MAX_EXTENSIONS, MAX_PLY};
+    use shakmaty::{Color, Move, MoveList, Role, Square};
+
+    #[test]
+    fn test_noisy_see_threshold() {
+        assert_eq!(noisy_see_threshold(300, 0), -300);
+        assert_eq!(noisy_see_threshold(300, 100), -400);
+        assert_eq!(noisy_see_threshold(300, -100), -200);
+        // Bad history can tighten the threshold, but not past zero
+        assert_eq!(noisy_see_threshold(300, -500), 0);
+        assert_eq!(noisy_see_threshold(i32::MAX, i32::MAX), i32::MIN);
+    }
+
+    #[test]
+    fn test_null_move_reduction() {
+        let params = Parameters::default();
+        // The defaults are the old 3 + depth / 3 + min((eval - beta) / 200, 4)
+        assert_eq!(null_move_reduction(&params, 9, 100, 0), 6);
+        assert_eq!(null_move_reduction(&params, 12, 450, 0), 9);
+        assert_eq!(null_move_reduction(&params, 20, 5000, 0), 13);
+
+        for values in [
+            ["1", "1", "50", "0"],
+            ["3", "3", "200", "4"],
+            ["6", "6", "400", "8"],
+        ] {
+            let mut params = Parameters::default();
+            for (name, value) in [
+                "nmp_base",
+                "nmp_depth_divisor",
+                "nmp_eval_divisor",
+                "nmp_eval_max",
+            ]
+            .into_iter()
+            .zip(values)
+            {
+                assert!(params.set(name, value));
+            }
+            for depth in 1..MAX_PLY as i32 {
+                for eval in [1, 199, 200, 1000, Score::MAX_EVAL] {
+                    let r = null_move_reduction(&params, depth, eval, 0);
+                    assert!(
+                        (1..=depth).contains(&r),
+                        "{values:?} depth {depth} eval {eval}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_promotion_not_futility_pruned() {
+        // After 1. c7 h6, c8=Q# mates, but the killer Ka5 is searched first and fails low
+        let mut board = Board::new("k7/7p/1KP5/8/8/8/8/8 w - - 0 1").unwrap();
+        for uci in ["c6c7", "h7h6"] {
+            let mv = board
+                .legal_moves()
+                .into_iter()
+                .find(|mv| board.to_uci(mv).to_string() == uci)
+                .unwrap();
+            board.make_move::<false>(&mv);
+        }
+        let tt = TranspositionTable::new(1);
+        let mut params = Parameters::default();
+        // Keep the node out of RFP and razoring, and futility prune anything below alpha
+        for (name, value) in [
+            ("rfp_depth", "1"),
+            ("razoring_depth", "1"),
+            ("fp_margin", "0"),
+            ("fp_fixed_margin", "0"),
+        ] {
+            assert!(params.set(name, value));
+        }
+        let mut search_params = SearchParams::default();
+        let mut search_info = SearchInfo::new();
+        search_info.killers[2] = board
+            .legal_moves()
+            .into_iter()
+            .find(|mv| board.to_uci(mv).to_string() == "b6a5");
+        let mut refs = SearchRefs::for_test(
+            &mut board,
+            params,
+            &mut search_params,
+            &mut search_info,
+            &tt,
+        );
+        // No static evaluation reaches alpha, so every frontier quiet is futile
+        let alpha = Score::TB_WIN;
+        let score = Search::alpha_beta(&mut refs, 2, alpha, alpha + 1);
+        assert!(score > alpha);
+    }
+
+    #[test]
+    fn test_quiet_check_behind_futile_quiet() {
+        // After 1. Kf1 Kg8, Ra8# mates, but Rb2 has so much history that it is sorted first
+        let find = |board: &Board, uci: &str| {
+            board
+                .legal_moves()
+                .into_iter()
+                .find(|mv| board.to_uci(mv).to_string() == uci)
+                .unwrap()
+        };
+        let mut board = Board::new("7k/5ppp/8/8/8/8/R7/6K1 w - - 0 1").unwrap();
+        for uci in ["g1f1", "h8g8"] {
+            let mv = find(&board, uci);
+            board.make_move::<false>(&mv);
+        }
+        let tt = TranspositionTable::new(1);
+        let mut params = Parameters::default();
+        for (name, value) in [
+            ("rfp_depth", "1"),
+            ("razoring_depth", "1"),
+            ("fp_margin", "0"),
+            ("fp_fixed_margin", "0"),
+            ("ordering_main", "160"),
+        ] {
+            assert!(params.set(name, value));
+        }
+        let mut search_params = SearchParams::default();
+        let mut search_info = SearchInfo::new();
+        search_info.killers[2] = Some(find(&board, "f1e1"));
+        let futile = find(&board, "a2b2");
+        for _ in 0..100 {
+            search_info
+                .history
+                .update_main(&params, Color::White, &futile, &MoveList::new(), 10);
+        }
+        let mate = find(&board, "a2a8");
+        let mut refs = SearchRefs::for_test(
+            &mut board,
+            params,
+            &mut search_params,
+            &mut search_info,
+            &tt,
+        );
+        let mut moves = refs.board.legal_moves();
+        Search::sort_moves(&mut moves, &None, &None, &refs);
+        let position = |mv: &Move| moves.iter().position(|m| m == mv).unwrap();
+        assert!(position(&futile) < position(&mate));
+
+        // No static evaluation reaches alpha, so Rb2 is futile while the mate behind it isn't
+        let alpha = Score::TB_WIN;
+        let score = Search::alpha_beta(&mut refs, 2, alpha, alpha + 1);
+        assert!(score > alpha);
+    }
+
+    #[test]
+    fn test_multicut_prunes_cut_node() {
+        // White can't be mated, so every move clears a beta just above a tablebase loss
+        let mut board = Board::new("4k3/8/8/8/8/8/8/QR2K3 b - - 0 1").unwrap();
+        let mv = board
+            .legal_moves()
+            .into_iter()
+            .find(|mv| board.to_uci(mv).to_string() == "e8d8")
+            .unwrap();
+        board.make_move::<false>(&mv);
+
+        let tt = TranspositionTable::new(1);
+        let mut params = Parameters::default();
+        // Leave the cutoff to multi-cut, with its reduced searches dropping straight into qsearch
+        for (name, value) in [
+            ("UCI_NullMove", "false"),
+            ("UCI_Futility", "false"),
+            ("razoring_depth", "1"),
+            ("iir_depth", "10"),
+            ("multicut_depth", "4"),
+            ("multicut_reduction", "3"),
+        ] {
+            assert!(params.set(name, value));
+        }
+        let mut search_params = SearchParams::default();
+        let mut search_info = SearchInfo::new();
+        let mut refs = SearchRefs::for_test(
+            &mut board,
+            params,
+            &mut search_params,
+            &mut search_info,
+            &tt,
+        );
+        let beta = -Score::TB_WIN;
+        let score = Search::alpha_beta(&mut refs, 4, beta - 1, beta);
+        assert_eq!(score, beta);
+        assert_eq!(refs.search_info.stats.multicut_cutoffs, 1);
+    }
+
+    #[test]
+    fn test_multicut_child_extension_budget() {
+        let mut board = Board::new("4k3/8/8/8/8/8/8/QR2K3 b - - 0 1").unwrap();
+        let mv = board
+            .legal_moves()
+            .into_iter()
+            .find(|mv| board.to_uci(mv).to_string() == "e8d8")
+            .unwrap();
+        board.make_move::<false>(&mv);
+
+        let tt = TranspositionTable::new(1);
+        let mut params = Parameters::default();
+        for (name, value) in [
+            ("UCI_NullMove", "false"),
+            ("UCI_Futility", "false"),
+            ("razoring_depth", "1"),
+            ("iir_depth", "10"),
+            ("multicut_depth", "4"),
+            ("multicut_reduction", "3"),
+        ] {
+            assert!(params.set(name, value));
+        }
+        let mut search_params = SearchParams::default();
+        let mut search_info = SearchInfo::new();
+        // A sibling searched earlier left its budget used up
+        search_info.extensions[1] = 3;
+        search_info.extensions[2] = MAX_EXTENSIONS;
+        let mut refs = SearchRefs::for_test(
+            &mut board,
+            params,
+            &mut search_params,
+            &mut search_info,
+            &tt,
+        );
+        let beta = -Score::TB_WIN;
+        assert_eq!(Search::alpha_beta(&mut refs, 4, beta - 1, beta), beta);
+        assert_eq!(refs.search_info.stats.multicut_cutoffs, 1);
+        // The children start from this node's budget, not what the sibling left behind
+        assert_eq!(refs.search_info.extensions[2], 3);
+    }
+
+    #[test]
+    fn test_razoring_verifies_close_fail_low() {
+        // Ra8# is quiet, so quiescence sees nothing better than the static evaluation
+        let mut board = Board::new("6k1/5ppp/8/8/8/8/8/R5Kn b - - 0 1").unwrap();
+        let mv = board
+            .legal_moves()
+            .into_iter()
+            .find(|mv| board.to_uci(mv).to_string() == "h1g3")
+            .unwrap();
+        board.make_move::<false>(&mv);
+        let alpha = board.evaluate();
+
+        let search = |board: &mut Board, verify_margin: &str| {
+            let tt = TranspositionTable::new(1);
+            let mut params = Parameters::default();
+            // Razor as soon as the evaluation doesn't beat alpha
+            for (name, value) in [
+                ("UCI_NullMove", "false"),
+                ("UCI_Futility", "false"),
+                ("razoring_margin", "0"),
+                ("razoring_fixed_margin", "0"),
+                ("razoring_verify_margin", verify_margin),
+            ] {
+                assert!(params.set(name, value));
+            }
+            let mut search_params = SearchParams::default();
+            let mut search_info = SearchInfo::new();
+            let mut refs =
+                SearchRefs::for_test(board, params, &mut search_params, &mut search_info, &tt);
+            Search::alpha_beta(&mut refs, 2, alpha, alpha + 1)
+        };
+        // Without verification, the quiescence score is taken and the mate is missed
+        assert!(search(&mut board, "0") <= alpha);
+        let score = search(&mut board, "40");
+        assert!(Score::is_mate(score) && score > 0);
+    }
+
+    #[test]
+    fn test_colliding_tt_entry_ignored() {
+        // Qd5 is blocked by the pawn on d2, as if the entry had been stored by another position
+        let illegal = Move::Normal {
+            role: Role::Queen,
+            from: Square::D1,
+            capture: None,
+            to: Square::D5,
+            promotion: None,
+        };
+        let search = |collision: bool| {
+            let mut board = Board::starting_position();
+            let tt = TranspositionTable::new(1);
+            if collision {
+                let hash = board.get_hash();
+                tt.write(hash, 30, 1234, Bound::Exact, Some(illegal.clone()), 0);
+            }
+            let mut search_params = SearchParams::from_go("go depth 3");
+            let mut search_info = SearchInfo::new();
+            let mut refs = SearchRefs::for_test(
+                &mut board,
+                Parameters::default(),
+                &mut search_params,
+                &mut search_info,
+                &tt,
+            );
+            // A null window makes the root a cut node, where the entry would be a cutoff
+            let score = Search::alpha_beta(&mut refs, 3, -1, 0);
+            let result = Search::iterative_deepening(&mut refs);
+            (score, result.best_move)
+        };
+
+        let (score, best_move) = search(true);
+        assert_ne!(score, 1234);
+        assert_eq!((score, best_move.clone()), search(false));
+        assert_ne!(best_move, Some(illegal));
+    }
+
+    #[test]
+    fn test_pawn_push_extension() {
+        // e7 and e8=Q# win the race against the a-pawn, one ply deeper than the search goes
+        let search = |pawn_push_material: &str| {
+            let mut board = Board::new("7k/5K1p/4P2P/p7/8/8/8/8 w - - 0 1").unwrap();
+            let tt = TranspositionTable::new(1);
+            let mut params = Parameters::default();
+            for (name, value) in [
+                ("UCI_NullMove", "false"),
+                ("UCI_Futility", "false"),
+                ("pawn_push_material", pawn_push_material),
+            ] {
+                assert!(params.set(name, value));
+            }
+            let mut search_params = SearchParams::default();
+            let mut search_info = SearchInfo::new();
+            let mut refs = SearchRefs::for_test(
+                &mut board,
+                params,
+                &mut search_params,
+                &mut search_info,
+                &tt,
+            );
+            Search::alpha_beta(&mut refs, 2, -Score::INFINITY, Score::INFINITY)
+        };
+        assert!(!Score::is_mate(search("0")));
+        assert_eq!(search("2600"), Score::MATE - 3);
+    }
+
+    #[test]
+    fn test_mate_scores_not_clamped() {
+        let mut board = Board::new("7k/8/8/8/8/8/R7/1R4K1 w - - 0 1").unwrap();
+        let root = board.get_hash();
+        let tt = TranspositionTable::new(1);
+        let mut params = Parameters::default();
+        for name in ["UCI_NullMove", "UCI_LMR", "UCI_Futility", "UCI_SEEPruning"] {
+            assert!(params.set(name, "false"));
+        }
+        let mut search_params = SearchParams::default();
+        let mut search_info = SearchInfo::new();
+        let mut refs = SearchRefs::for_test(
+            &mut board,
+            params,
+            &mut search_params,
+            &mut search_info,
+            &tt,
+        );
+        // Static evaluations stop at MAX_EVAL, a mate in two is well past it
+        let score = Search::alpha_beta(&mut refs, 4, -Score::INFINITY, Score::INFINITY);
+        assert_eq!(score, Score::MATE - 3);
+        assert!(Score::is_win(score));
+
+        // The TT keeps the distance relative to where the entry is read
+        let hit = refs.tt.read(root, 0).unwrap();
+        assert_eq!(hit.score, Score::MATE - 3);
+        assert_eq!(refs.tt.read(root, 2).unwrap().score, Score::MATE - 5);
+    }
+}
@@ -1,53 +1,473 @@
 use super::history::History;
 use crate::board::Board;
-use crate::transposition::TranspositionTable;
+use crate::tablebase::Tablebases;
+use crate::transposition::{Entry, TranspositionTable};
 use crate::types::parameters::Parameters;
-use crate::types::MAX_PLY;
-use shakmaty::Move;
-use std::time::Instant;
+use crate::types::{Score, MAX_PLY};
+use crossbeam_channel::Sender;
+use shakmaty::{Color, Move};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Milliseconds into the search after which intermediate info (current move, bounds) is reported.
+pub const INFO_INTERVAL: u128 = 1000;
+
+/// Nodes searched between two looks at the clock and the stop flag. Must be a power of two.
+pub const DEFAULT_CHECK_INTERVAL: usize = 2048;
+
+/// Moves the remaining time is spread over when `go` gives no `movestogo`.
+const SUDDEN_DEATH_MOVES: u128 = 20;
+/// Share of the remaining time a single move may take at most, besides the increment.
+const HARD_TIME_DIVISOR: u128 = 4;
+
+/// Source of time for the search, so that tests can substitute a clock they control.
+pub trait Clock: Send {
+    /// Time since an arbitrary fixed point.
+    fn now(&self) -> Duration;
+}
+
+/// Wall clock time, measured from when the clock was created.
+pub struct SystemClock(Instant);
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self(Instant::now())
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.0.elapsed()
+    }
+}
 
 pub struct SearchInfo {
-    start_time: Option<Instant>,
+    clock: Box<dyn Clock>,
+    /// When `go` started the search. Everything reported to the GUI is timed from here.
+    start_time: Option<Duration>,
+    /// When the time budget started, which is later than `start_time` after a ponderhit.
+    budget_start: Option<Duration>,
+    /// Nodes between time checks, see `DEFAULT_CHECK_INTERVAL`.
+    pub check_interval: usize,
+    /// Nodes searched by this thread.
     pub nodes: usize,
+    /// Nodes of every thread working on the same search. Each adds its own in batches, at
+    /// every time check and once its search ends, so that threads rarely touch it.
+    pub shared_nodes: Arc<AtomicU64>,
+    /// Part of `nodes` already added to `shared_nodes`.
+    flushed_nodes: usize,
+    /// Deepest ply reached so far, quiescence search included. Zero when a search begins,
+    /// see `next_search`.
     pub sel_depth: usize,
     pub cp: i32,
+    /// Kept from one search to the next, like `history`.
     pub killers: Vec<Option<Move>>,
+    /// Move skipped by the node at each ply, for singular extensions and MultiPV. Whoever sets
+    /// an entry clears it again once the search of that node returns.
+    pub excluded: Vec<Option<Move>>,
     pub terminated: bool,
+    pub completed_depth: i32,
+    /// Set once a root move has been searched to completion. Time is only checked afterwards.
+    pub root_move_searched: bool,
+    /// Best root move of the current iteration so far, even if it didn't raise alpha. What
+    /// an interrupted first iteration falls back to.
+    pub best_root_move: Option<Move>,
     pub pv: Vec<Vec<Option<Move>>>,
     pub pv_length: [usize; MAX_PLY],
+    /// Number of plies extended along the current line, indexed by ply.
+    pub extensions: [i32; MAX_PLY],
+    /// Kept from one search to the next, decayed, see `next_search`.
     pub history: History,
+    pub stats: SearchStats,
 }
 
 impl SearchInfo {
     pub fn new() -> Self {
+        Self::with_clock(Box::<SystemClock>::default())
+    }
+
+    pub fn with_clock(clock: Box<dyn Clock>) -> Self {
         Self {
+            clock,
             start_time: None,
+            budget_start: None,
+            check_interval: DEFAULT_CHECK_INTERVAL,
             nodes: 0,
+            shared_nodes: Arc::new(AtomicU64::new(0)),
+            flushed_nodes: 0,
             sel_depth: 0,
             cp: 0,
             killers: vec![None; MAX_PLY],
+            excluded: vec![None; MAX_PLY],
             terminated: false,
+            completed_depth: 0,
+            root_move_searched: false,
+            best_root_move: None,
             pv: vec![vec![None; MAX_PLY]; MAX_PLY],
             pv_length: [0; MAX_PLY],
+            extensions: [0; MAX_PLY],
             history: History::default(),
+            stats: SearchStats::default(),
         }
     }
 
+    /// Gets ready for the next search of the same game. The move ordering heuristics carry
+    /// over, since most of what they learnt still holds a move later, with history halved so
+    /// that the new position soon outweighs the old one. The shared node counter is kept too,
+    /// so that other threads holding it follow along, but counts from zero again. Everything
+    /// else starts over.
+    pub fn next_search(self) -> Self {
+        let mut history = self.history;
+        history.age();
+        self.shared_nodes.store(0, Ordering::Relaxed);
+        Self {
+            killers: self.killers,
+            history,
+            shared_nodes: self.shared_nodes,
+            check_interval: self.check_interval,
+            ..Self::with_clock(self.clock)
+        }
+    }
+
+    /// The principal variation of the last root search, from the triangular PV table. Only
+    /// moves the search actually played on its way to the score, unlike a walk through TT
+    /// moves, which may follow bounds and entries from other searches.
+    pub fn root_pv(&self) -> Vec<Move> {
+        self.pv[0][..self.pv_length[0]]
+            .iter()
+            .map_while(Clone::clone)
+            .collect()
+    }
+
     pub fn start(&mut self) {
-        self.start_time = Some(Instant::now());
+        let now = self.clock.now();
+        self.start_time = Some(now);
+        self.budget_start = Some(now);
+    }
+
+    /// Starts the time budget over without touching the reported time, for a ponderhit.
+    pub fn restart_budget(&mut self) {
+        self.budget_start = Some(self.clock.now());
+    }
+
+    /// Milliseconds since the search started. Only `start` resets it, so the `time` of
+    /// consecutive info lines never goes backwards.
+    pub fn elapsed(&self) -> u128 {
+        self.elapsed_duration().as_millis()
+    }
+
+    /// Milliseconds of the time budget used, which is what the time limits are checked against.
+    pub fn budget_elapsed(&self) -> u128 {
+        self.since(self.budget_start).as_millis()
+    }
+
+    fn elapsed_duration(&self) -> Duration {
+        self.since(self.start_time)
+    }
+
+    fn since(&self, start: Option<Duration>) -> Duration {
+        match start {
+            Some(start) => self.clock.now().saturating_sub(start),
+            None => Duration::ZERO,
+        }
+    }
+
+    /// Adds the nodes searched since the last flush to the shared count.
+    pub fn flush_nodes(&mut self) {
+        let unflushed = self.nodes - self.flushed_nodes;
+        self.shared_nodes
+            .fetch_add(unflushed as u64, Ordering::Relaxed);
+        self.flushed_nodes = self.nodes;
+    }
+
+    /// Nodes of all threads, as reported to the GUI. Exact for this thread, and up to a time
+    /// check behind for the others.
+    pub fn total_nodes(&self) -> usize {
+        self.shared_nodes.load(Ordering::Relaxed) as usize + self.nodes - self.flushed_nodes
+    }
+
+    /// Nodes per second so far. Measured in microseconds, so that very short searches
+    /// still get a meaningful figure.
+    pub fn nps(&self) -> u64 {
+        let micros = self.elapsed_duration().as_micros().max(1);
+        (self.total_nodes() as u128 * 1_000_000 / micros) as u64
+    }
+}
+
+/// Counters collected during a search, used to judge move ordering and pruning.
+#[derive(Clone, Default)]
+pub struct SearchStats {
+    pub beta_cutoffs: usize,
+    pub first_move_cutoffs: usize,
+    pub tt_probes: usize,
+    pub tt_hits: usize,
+    pub null_move_cutoffs: usize,
+    pub multicut_cutoffs: usize,
+    pub qsearch_nodes: usize,
+    pub tb_hits: usize,
+    /// Aspiration windows that failed and had to be searched again.
+    pub aspiration_researches: usize,
+}
+
+impl SearchStats {
+    /// Share of beta cutoffs caused by the first move searched, a measure of move ordering.
+    pub fn first_move_cutoff_rate(&self) -> f64 {
+        if self.beta_cutoffs > 0 {
+            self.first_move_cutoffs as f64 / self.beta_cutoffs as f64
+        } else {
+            0.0
+        }
     }
 
-    pub fn elapsed(&mut self) -> u128 {
-        match self.start_time {
-            Some(x) => x.elapsed().as_millis(),
-            None => 0,
+    /// Describes the counters as UCI `info string` lines.
+    pub fn lines(&self, nodes: usize) -> Vec<String> {
+        fn percent(part: usize, total: usize) -> f64 {
+            if total > 0 {
+                100.0 * part as f64 / total as f64
+            } else {
+                0.0
+            }
         }
+
+        vec![
+            format!(
+                "info string first move cutoffs {:.1}% ({} of {})",
+                100.0 * self.first_move_cutoff_rate(),
+                self.first_move_cutoffs,
+                self.beta_cutoffs
+            ),
+            format!(
+                "info string tt hits {:.1}% ({} of {})",
+                percent(self.tt_hits, self.tt_probes),
+                self.tt_hits,
+                self.tt_probes
+            ),
+            format!("info string null move cutoffs {}", self.null_move_cutoffs),
+            format!("info string multi-cut cutoffs {}", self.multicut_cutoffs),
+            format!("info string tablebase hits {}", self.tb_hits),
+            format!(
+                "info string aspiration re-searches {}",
+                self.aspiration_researches
+            ),
+            format!(
+                "info string qsearch nodes {:.1}% ({} of {})",
+                percent(self.qsearch_nodes, nodes),
+                self.qsearch_nodes,
+                nodes
+            ),
+        ]
     }
 }
 
+/// What the search thread sends back to whoever started it, in the order it happened. Nothing
+/// is printed by the search itself.
+pub enum SearchReport {
+    /// A UCI `info` line, like the progress of each depth or the answer to `stats`.
+    Info(String),
+    /// The outcome of a search, which is always the last report of that search.
+    Done(SearchResult),
+}
+
+/// Outcome of a search, sent back to whoever started it.
+#[derive(Clone)]
+pub struct SearchResult {
+    pub best_move: Option<Move>,
+    pub score: i32,
+    pub pv: Vec<Move>,
+    pub depth: i32,
+    pub nodes: usize,
+}
+
+/// Clocks from `go`, in milliseconds and indexed by color. Only read when the search has
+/// no fixed time.
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct TimeControl {
+    pub time: [Option<u128>; 2],
+    pub increment: [u128; 2],
+    pub moves_to_go: Option<u128>,
+}
+
 pub struct SearchParams {
+    /// Iterative deepening stops before this depth, so `go depth N` stores `N + 1`.
     pub depth: i32,
+    /// Nodes after which the search stops, if capped.
+    pub nodes: Option<usize>,
     pub search_time: u128,
+    /// Optimum time for the move. Iterative deepening scales it by best move stability
+    /// and score trend, and won't start a new depth once the scaled bound is exceeded.
+    pub soft_time: Option<u128>,
+    /// Clocks to budget `search_time` and `soft_time` from, once the side to move is known.
+    pub time_control: TimeControl,
+    /// Searching on the opponent's time. Limits only apply once `ponderhit` arrives.
+    pub ponder: bool,
+    /// Report win/draw/loss probabilities alongside the score.
+    pub show_wdl: bool,
+    /// Follow each completed depth with its PV in SAN, as an `info string`.
+    pub show_san: bool,
+    /// UCI `debug on`. Diagnostics like the root evaluation, the search limits and the
+    /// search stats are only reported as `info string` when set.
+    pub debug: bool,
+    /// Nudge repetition and material draws by a couple of centipawns, so that equal-looking
+    /// lines aren't all the same to the search. On by default in the engine, except in
+    /// fixed-node searches. `SearchParams::default()` leaves it off so test searches stay exact.
+    pub draw_jitter: bool,
+}
+
+impl SearchParams {
+    /// Builds the limits for a single search from the tokens of a `go` command. Tokens that
+    /// aren't understood are skipped, so any well formed `go` line starts a search.
+    ///
+    /// `infinite` lifts the time limit but combines with `depth` and `nodes`, in any order.
+    /// The search ends at whichever comes first: `stop`, then a cap, and only then does
+    /// `infinite` mean searching forever.
+    pub fn from_go(cmd: &str) -> Self {
+        let mut params = Self::default();
+        let mut infinite = false;
+        let mut movetime = false;
+        let mut tokens = cmd.split_whitespace().skip(1);
+        while let Some(token) = tokens.next() {
+            match token {
+                "movetime" => {
+                    if let Some(Ok(time)) = tokens.next().map(str::parse::<u128>) {
+                        params.search_time = time;
+                        movetime = true;
+                    }
+                }
+                "wtime" => {
+                    params.time_control.time[Color::White as usize] =
+                        tokens.next().and_then(parse_clock);
+                }
+                "btime" => {
+                    params.time_control.time[Color::Black as usize] =
+                        tokens.next().and_then(parse_clock);
+                }
+                "winc" => {
+                    params.time_control.increment[Color::White as usize] =
+                        tokens.next().and_then(parse_clock).unwrap_or(0);
+                }
+                "binc" => {
+                    params.time_control.increment[Color::Black as usize] =
+                        tokens.next().and_then(parse_clock).unwrap_or(0);
+                }
+                "movestogo" => {
+                    params.time_control.moves_to_go = tokens.next().and_then(parse_clock);
+                }
+                "depth" => {
+                    if let Some(Ok(depth)) = tokens.next().map(str::parse::<i32>) {
+                        params.depth = depth.clamp(1, MAX_PLY as i32 - 2) + 1;
+                    }
+                }
+                "nodes" => {
+                    if let Some(nodes) = tokens.next().and_then(parse_count) {
+                        params.nodes = Some(nodes);
+                    }
+                }
+                "infinite" => infinite = true,
+                "ponder" => params.ponder = true,
+                _ => (),
+            }
+        }
+        if infinite {
+            params.search_time = u128::MAX;
+            params.soft_time = None;
+        }
+        if infinite || movetime {
+            params.time_control = TimeControl::default();
+        }
+        params
+    }
+
+    /// Budgets the move from the clock of the side to move, if `go` gave one. The soft bound
+    /// spreads the time over `movestogo` moves, or `SUDDEN_DEATH_MOVES` without it, plus most
+    /// of the increment. The hard bound is a share of the clock that never exceeds half of it,
+    /// so that a single move can't lose on time.
+    pub fn allocate_time(&mut self, turn: Color) {
+        let Some(time) = self.time_control.time[turn as usize] else {
+            return;
+        };
+        let increment = self.time_control.increment[turn as usize] * 3 / 4;
+        let moves = self
+            .time_control
+            .moves_to_go
+            .map_or(SUDDEN_DEATH_MOVES, |moves| moves.max(1));
+
+        self.search_time = (time / HARD_TIME_DIVISOR + increment).min(time / 2);
+        self.soft_time = Some((time / moves + increment).min(self.search_time));
+    }
+
+    /// Describes the limits a search ran with, for bug reports.
+    pub fn summary(&self) -> String {
+        let time = match self.search_time {
+            u128::MAX => "infinite".to_string(),
+            time => format!("{time}ms"),
+        };
+        let soft_time = match self.soft_time {
+            Some(time) => format!("{time}ms"),
+            None => "none".to_string(),
+        };
+        let nodes = match self.nodes {
+            Some(nodes) => format!(" nodes {nodes}"),
+            None => String::new(),
+        };
+        format!(
+            "time {} soft {} depth {}{}{}",
+            time,
+            soft_time,
+            self.depth,
+            nodes,
+            if self.ponder { " ponder" } else { "" }
+        )
+    }
+
+    /// Whether the search runs until told otherwise, as in analysis (`go infinite`) or while
+    /// pondering, rather than until its own limits say it's done.
+    pub fn is_open_ended(&self) -> bool {
+        self.ponder || self.search_time == u128::MAX
+    }
+
+    /// Reserves time for communication latency. The budget saturates at zero, and an infinite
+    /// search stays infinite.
+    pub fn apply_overhead(&mut self, overhead: u128) {
+        if self.search_time != u128::MAX {
+            self.search_time = self.search_time.saturating_sub(overhead);
+        }
+        self.soft_time = self.soft_time.map(|time| time.saturating_sub(overhead));
+    }
+}
+
+impl Default for SearchParams {
+    fn default() -> Self {
+        Self {
+            depth: 69,
+            nodes: None,
+            search_time: 600000,
+            soft_time: None,
+            time_control: TimeControl::default(),
+            ponder: false,
+            show_wdl: false,
+            show_san: false,
+            debug: false,
+            draw_jitter: false,
+        }
+    }
+}
+
+/// Parses a clock time or increment. Some GUIs send a negative time once the clock has run
+/// out, which counts as none left.
+fn parse_clock(token: &str) -> Option<u128> {
+    token.parse::<i64>().ok().map(|time| time.max(0) as u128)
+}
+
+/// Parses a count such as a node limit, also taking scientific notation like `1e8`.
+fn parse_count(token: &str) -> Option<usize> {
+    token.parse().ok().or_else(|| {
+        token
+            .parse::<f64>()
+            .ok()
+            .filter(|count| count.is_finite() && *count >= 0.0)
+            .map(|count| count as usize)
+    })
 }
 
 pub struct SearchRefs<'a> {
@@ -55,6 +475,456 @@ pub struct SearchRefs<'a> {
     pub params: Parameters,
     pub search_params: &'a mut SearchParams,
     pub search_info: &'a mut SearchInfo,
-    pub tt: &'a mut TranspositionTable,
+    pub tt: &'a TranspositionTable,
     pub tt_enabled: bool,
+    pub tablebase: Option<&'a Tablebases>,
+    pub stop: &'a AtomicBool,
+    pub ponderhit: &'a AtomicBool,
+    /// Where `info` lines go. Without one they are dropped.
+    pub reports: Option<&'a Sender<SearchReport>>,
+}
+
+#[cfg(test)]
+impl<'a> SearchRefs<'a> {
+    /// References for a search in a test: the TT enabled, no tablebases, stop and ponderhit
+    /// flags that are never raised, and no reports.
+    pub fn for_test(
+        board: &'a mut Board,
+        params: Parameters,
+        search_params: &'a mut SearchParams,
+        search_info: &'a mut SearchInfo,
+        tt: &'a TranspositionTable,
+    ) -> Self {
+        static LOWERED: AtomicBool = AtomicBool::new(false);
+        Self {
+            board,
+            params,
+            search_params,
+            search_info,
+            tt,
+            tt_enabled: true,
+            tablebase: None,
+            stop: &LOWERED,
+            ponderhit: &LOWERED,
+            reports: None,
+        }
+    }
+}
+
+impl SearchRefs<'_> {
+    /// Sends a UCI `info` line to whoever started the search.
+    pub fn report(&self, line: String) {
+        if let Some(reports) = self.reports {
+            // Nobody listening is not an error for the search itself
+            let _ = reports.send(SearchReport::Info(line));
+        }
+    }
+
+    /// Periodically checks the clock and the stop flag, and flags the search as terminated once
+    /// either says so. The first root move always completes so that there is a move to play.
+    pub fn check_time(&mut self) -> bool {
+        debug_assert!(self.search_info.check_interval.is_power_of_two());
+        if (self.search_info.nodes & (self.search_info.check_interval - 1)) == 0 {
+            self.search_info.flush_nodes();
+            self.check_ponderhit();
+            if self.search_info.root_move_searched
+                && (self.stop.load(Ordering::Relaxed)
+                    || !self.search_params.ponder
+                        && self.search_info.budget_elapsed() > self.search_params.search_time)
+            {
+                self.search_info.terminated = true;
+            }
+        }
+        // Node caps are checked on every node, so that they are exact
+        if self.search_info.root_move_searched
+            && self
+                .search_params
+                .nodes
+                .is_some_and(|nodes| self.search_info.nodes >= nodes)
+        {
+            self.search_info.terminated = true;
+        }
+        self.search_info.terminated
+    }
+
+    /// Score for a draw found by the search. The jitter comes from the node count, so it's
+    /// reproducible for a given search while still varying between sibling lines.
+    pub fn draw_score(&self) -> i32 {
+        if self.search_params.draw_jitter {
+            Score::DRAW + 2 - (self.search_info.nodes & 3) as i32
+        } else {
+            Score::DRAW
+        }
+    }
+
+    /// Reads the TT entry stored under `hash` for the current position. An entry whose move
+    /// is illegal here belongs to another position with a colliding hash, so it's a miss.
+    pub fn probe_tt(&self, hash: u64) -> Option<Entry> {
+        let hit = self.tt.read(hash, self.board.ply())?;
+        match &hit.mv {
+            Some(mv) if !self.board.is_legal(mv) => None,
+            _ => Some(hit),
+        }
+    }
+
+    /// Turns a ponder search into a normal one once the expected move is played.
+    /// The time budget starts over, since it belongs to the move after `ponderhit`, while the
+    /// reported time keeps counting from `go`.
+    pub fn check_ponderhit(&mut self) {
+        if self.search_params.ponder && self.ponderhit.load(Ordering::Relaxed) {
+            self.search_params.ponder = false;
+            self.search_info.restart_budget();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::board::Board;
+    use crate::search::defs::{Clock, SearchInfo, SearchParams, SearchRefs};
+    use crate::search::Search;
+    use crate::transposition::TranspositionTable;
+    use crate::types::parameters::Parameters;
+    use crate::types::Score;
+    use shakmaty::uci::UciMove;
+    use shakmaty::{Color, MoveList};
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_movetime_scoped_to_go() {
+        assert_eq!(SearchParams::from_go("go movetime 1000").search_time, 1000);
+
+        let default = SearchParams::default();
+        let params = SearchParams::from_go("go wtime 60000 btime 60000");
+        assert_eq!(params.search_time, default.search_time);
+    }
+
+    #[test]
+    fn test_allocate_time() {
+        let budget = |cmd: &str, turn: Color| {
+            let mut params = SearchParams::from_go(cmd);
+            params.allocate_time(turn);
+            (params.search_time, params.soft_time)
+        };
+        // Sudden death spreads the clock over twenty moves, plus most of the increment
+        let cmd = "go wtime 60000 btime 30000 winc 1000 binc 0";
+        assert_eq!(budget(cmd, Color::White), (15750, Some(3750)));
+        assert_eq!(budget(cmd, Color::Black), (7500, Some(1500)));
+        // The clock is spread over the moves to the time control, and fewer of them leave more
+        // per move, up to half the clock
+        let cmd = "go wtime 60000 btime 60000 movestogo 4";
+        assert_eq!(budget(cmd, Color::White), (15000, Some(15000)));
+        let cmd = "go wtime 60000 btime 60000 winc 1000 movestogo 40";
+        assert_eq!(budget(cmd, Color::White), (15750, Some(2250)));
+        assert_eq!(budget(cmd, Color::Black), (15000, Some(1500)));
+
+        // A large increment can't push either bound past half of what's left
+        assert_eq!(
+            budget("go wtime 100 btime 100 winc 1000", Color::White),
+            (50, Some(50))
+        );
+        assert_eq!(budget("go wtime -20 btime 100", Color::White), (0, Some(0)));
+
+        // A fixed time wins, and a missing clock leaves the defaults
+        assert_eq!(
+            budget("go wtime 60000 btime 60000 movetime 500", Color::White),
+            (500, None)
+        );
+        let default = SearchParams::default();
+        assert_eq!(
+            budget("go btime 60000", Color::White),
+            (default.search_time, None)
+        );
+    }
+
+    #[test]
+    fn test_go_forms() {
+        let default = SearchParams::default();
+        assert_eq!(SearchParams::from_go("go").search_time, default.search_time);
+        assert_eq!(SearchParams::from_go("go movetime 1000").search_time, 1000);
+        let params = SearchParams::from_go("go wtime 60000 btime 60000");
+        assert_eq!(params.depth, default.depth);
+        assert!(!params.ponder);
+    }
+
+    #[test]
+    fn test_go_ignores_unknown_tokens() {
+        let params = SearchParams::from_go("go nodes 5000 movetime 250 searchmoves e2e4 mate 3");
+        assert_eq!(params.search_time, 250);
+        assert_eq!(params.depth, SearchParams::default().depth);
+    }
+
+    #[test]
+    fn test_go_infinite_with_caps() {
+        let params = SearchParams::from_go("go infinite");
+        assert_eq!(params.search_time, u128::MAX);
+        assert_eq!(params.depth, SearchParams::default().depth);
+        assert_eq!(params.nodes, None);
+        assert!(params.is_open_ended());
+        assert!(SearchParams::from_go("go ponder wtime 1000 btime 1000").is_open_ended());
+        assert!(!SearchParams::from_go("go wtime 1000 btime 1000").is_open_ended());
+
+        for cmd in ["go infinite depth 30", "go depth 30 infinite"] {
+            let params = SearchParams::from_go(cmd);
+            assert_eq!(params.search_time, u128::MAX, "{cmd}");
+            assert_eq!(params.depth, 31, "{cmd}");
+        }
+        let params = SearchParams::from_go("go infinite nodes 1e8");
+        assert_eq!(params.nodes, Some(100_000_000));
+        // Overhead can't make an infinite search finite
+        let mut params = params;
+        params.apply_overhead(10);
+        assert_eq!(
+            params.summary(),
+            "time infinite soft none depth 69 nodes 100000000"
+        );
+        // Infinite wins over a time limit, not over a cap
+        let params = SearchParams::from_go("go movetime 100 infinite nodes 5000");
+        assert_eq!(params.search_time, u128::MAX);
+        assert_eq!(params.nodes, Some(5000));
+    }
+
+    #[test]
+    fn test_node_cap() {
+        let mut board = Board::starting_position();
+        let tt = TranspositionTable::new(1);
+        let mut search_params = SearchParams::from_go("go infinite nodes 3000");
+        let mut search_info = SearchInfo::new();
+        let mut refs = SearchRefs::for_test(
+            &mut board,
+            Parameters::default(),
+            &mut search_params,
+            &mut search_info,
+            &tt,
+        );
+        let result = Search::iterative_deepening(&mut refs);
+        assert!(result.best_move.is_some());
+        assert_eq!(result.nodes, 3000);
+
+        // A depth cap ends an infinite search too
+        let mut search_params = SearchParams::from_go("go infinite depth 3");
+        refs.search_params = &mut search_params;
+        let mut search_info = SearchInfo::new();
+        refs.search_info = &mut search_info;
+        assert_eq!(Search::iterative_deepening(&mut refs).depth, 3);
+    }
+
+    #[test]
+    fn test_shared_nodes() {
+        let mut first = SearchInfo::new();
+        let mut second = SearchInfo::new();
+        second.shared_nodes = Arc::clone(&first.shared_nodes);
+
+        first.nodes = 1500;
+        assert_eq!(second.total_nodes(), 0);
+        first.flush_nodes();
+        second.nodes = 700;
+        assert_eq!(second.total_nodes(), 2200);
+        assert_eq!(first.total_nodes(), 1500);
+        second.flush_nodes();
+        assert_eq!(first.total_nodes(), 2200);
+        // Flushing twice adds nothing
+        second.flush_nodes();
+        assert_eq!(first.shared_nodes.load(Ordering::Relaxed), 2200);
+
+        // A search flushes at every time check, so only what a final flush adds is missing
+        let mut board = Board::starting_position();
+        let tt = TranspositionTable::new(1);
+        let mut search_params = SearchParams::from_go("go depth 4");
+        let mut search_info = SearchInfo::new();
+        search_info.check_interval = 64;
+        let mut refs = SearchRefs::for_test(
+            &mut board,
+            Parameters::default(),
+            &mut search_params,
+            &mut search_info,
+            &tt,
+        );
+        let result = Search::iterative_deepening(&mut refs);
+        assert_eq!(result.nodes, search_info.nodes);
+        let flushed = search_info.shared_nodes.load(Ordering::Relaxed) as usize;
+        assert!(search_info.nodes - flushed < 64);
+        search_info.flush_nodes();
+        assert_eq!(
+            search_info.shared_nodes.load(Ordering::Relaxed) as usize,
+            search_info.nodes
+        );
+    }
+
+    #[test]
+    fn test_next_search() {
+        let mut board = Board::starting_position();
+        let tt = TranspositionTable::new(1);
+        let mut search_params = SearchParams::from_go("go depth 4");
+        let mut search_info = SearchInfo::new();
+        let mut refs = SearchRefs::for_test(
+            &mut board,
+            Parameters::default(),
+            &mut search_params,
+            &mut search_info,
+            &tt,
+        );
+        Search::iterative_deepening(&mut refs);
+        let mv = "g1f3".parse::<UciMove>().unwrap();
+        let mv = mv.to_move(board.chess().unwrap()).unwrap();
+        search_info.history.update_main(
+            &Parameters::default(),
+            Color::White,
+            &mv,
+            &MoveList::new(),
+            8,
+        );
+        let score = search_info.history.get_main(Color::White, &mv).unwrap();
+        let killers = search_info.killers.clone();
+        assert!(killers.iter().any(Option::is_some));
+        search_info.flush_nodes();
+        let shared_nodes = Arc::clone(&search_info.shared_nodes);
+        assert!(shared_nodes.load(Ordering::Relaxed) > 0);
+
+        let search_info = search_info.next_search();
+        assert_eq!(search_info.nodes, 0);
+        assert!(Arc::ptr_eq(&search_info.shared_nodes, &shared_nodes));
+        assert_eq!(search_info.total_nodes(), 0);
+        assert_eq!(search_info.sel_depth, 0);
+        assert_eq!(search_info.completed_depth, 0);
+        assert!(search_info.root_pv().is_empty());
+        assert_eq!(search_info.killers, killers);
+        assert_eq!(
+            search_info.history.get_main(Color::White, &mv),
+            Some(score / 2)
+        );
+    }
+
+    #[test]
+    fn test_go_ponder() {
+        assert!(SearchParams::from_go("go ponder movetime 1000").ponder);
+        assert!(!SearchParams::from_go("go movetime 1000").ponder);
+    }
+
+    #[test]
+    fn test_summary() {
+        let params = SearchParams::from_go("go movetime 1000");
+        assert_eq!(params.summary(), "time 1000ms soft none depth 69");
+    }
+
+    #[test]
+    fn test_draw_score() {
+        let mut board = Board::starting_position();
+        let tt = TranspositionTable::new(1);
+        let mut search_params = SearchParams::default();
+        let mut search_info = SearchInfo::new();
+        let refs = SearchRefs::for_test(
+            &mut board,
+            Parameters::default(),
+            &mut search_params,
+            &mut search_info,
+            &tt,
+        );
+        refs.search_info.nodes = 7;
+        assert_eq!(refs.draw_score(), Score::DRAW);
+
+        refs.search_params.draw_jitter = true;
+        let scores: Vec<i32> = (0..4)
+            .map(|nodes| {
+                refs.search_info.nodes = nodes;
+                refs.draw_score()
+            })
+            .collect();
+        assert_eq!(scores, [2, 1, 0, -1]);
+    }
+
+    /// Clock that only moves when the test says so.
+    struct ManualClock(Arc<AtomicU64>);
+
+    impl Clock for ManualClock {
+        fn now(&self) -> Duration {
+            Duration::from_millis(self.0.load(Ordering::Relaxed))
+        }
+    }
+
+    #[test]
+    fn test_check_time_with_manual_clock() {
+        let time = Arc::new(AtomicU64::new(0));
+        let mut board = Board::starting_position();
+        let tt = TranspositionTable::new(1);
+        let mut search_params = SearchParams::from_go("go movetime 100");
+        let mut search_info = SearchInfo::with_clock(Box::new(ManualClock(Arc::clone(&time))));
+        search_info.check_interval = 4;
+        search_info.root_move_searched = true;
+        let mut refs = SearchRefs::for_test(
+            &mut board,
+            Parameters::default(),
+            &mut search_params,
+            &mut search_info,
+            &tt,
+        );
+        refs.search_info.start();
+        time.store(100, Ordering::Relaxed);
+        assert_eq!(refs.search_info.elapsed(), 100);
+        assert!(!refs.check_time());
+
+        time.store(101, Ordering::Relaxed);
+        // The clock is only read every `check_interval` nodes
+        refs.search_info.nodes = 5;
+        assert!(!refs.check_time());
+        refs.search_info.nodes = 8;
+        assert!(refs.check_time());
+    }
+
+    #[test]
+    fn test_ponderhit_keeps_reported_time() {
+        let time = Arc::new(AtomicU64::new(0));
+        let mut board = Board::starting_position();
+        let tt = TranspositionTable::new(1);
+        let mut search_params = SearchParams::from_go("go ponder movetime 40");
+        let mut search_info = SearchInfo::with_clock(Box::new(ManualClock(Arc::clone(&time))));
+        search_info.check_interval = 1;
+        search_info.root_move_searched = true;
+        let ponderhit = AtomicBool::new(false);
+        let mut refs = SearchRefs::for_test(
+            &mut board,
+            Parameters::default(),
+            &mut search_params,
+            &mut search_info,
+            &tt,
+        );
+        refs.ponderhit = &ponderhit;
+        refs.search_info.start();
+        time.store(50, Ordering::Relaxed);
+        assert!(!refs.check_time());
+
+        ponderhit.store(true, Ordering::Relaxed);
+        assert!(!refs.check_time());
+        time.store(80, Ordering::Relaxed);
+        // The budget restarted at the ponderhit, the reported time didn't
+        assert_eq!(refs.search_info.elapsed(), 80);
+        assert_eq!(refs.search_info.budget_elapsed(), 30);
+        assert!(!refs.check_time());
+        time.store(91, Ordering::Relaxed);
+        assert!(refs.check_time());
+    }
+
+    #[test]
+    fn test_nps() {
+        let time = Arc::new(AtomicU64::new(0));
+        let mut search_info = SearchInfo::with_clock(Box::new(ManualClock(Arc::clone(&time))));
+        search_info.start();
+        search_info.nodes = 1500;
+        // Less than a millisecond in, which must not divide by zero
+        assert_eq!(search_info.nps(), 1_500_000_000);
+
+        time.store(3, Ordering::Relaxed);
+        assert_eq!(search_info.nps(), 500_000);
+    }
+
+    #[test]
+    fn test_move_overhead() {
+        let mut params = SearchParams::from_go("go movetime 100");
+        params.apply_overhead(10);
+        assert_eq!(params.search_time, 90);
+        params.apply_overhead(1000);
+        assert_eq!(params.search_time, 0);
+    }
 }
@@ -1,8 +1,6 @@
 use crate::search::Board;
-use crate::types::parameters::*;
-use shakmaty::{Chess, Color, Move, MoveList, Position, Role};
-
-const MAX_HISTORY: i32 = 16384;
+use crate::types::parameters::{Parameters, MAX_HISTORY};
+use shakmaty::{Color, Move, MoveList, Role};
 
 type Butterfly<T> = [[T; 64]; 64];
 type PieceSquare<T> = [[T; 64]; 8];
@@ -15,22 +13,32 @@ pub struct History {
     followup: Box<PieceSquare<PieceSquare<i32>>>,
 }
 
+/// Scores are read through here, so that callers can rely on `MAX_HISTORY` even if an update
+/// ever overshoots.
+fn bounded(score: i32) -> i32 {
+    score.clamp(-MAX_HISTORY, MAX_HISTORY)
+}
+
 impl History {
     pub fn get_capture(&self, side: Color, mv: &Move) -> Option<i32> {
-        Some(
+        Some(bounded(
             self.capture[side as usize][mv.from()? as usize][mv.to() as usize]
                 [mv.capture()? as usize],
-        )
+        ))
     }
 
     pub fn get_main(&self, stm: Color, mv: &Move) -> Option<i32> {
-        Some(self.main[stm as usize][mv.from()? as usize][mv.to() as usize])
+        Some(bounded(
+            self.main[stm as usize][mv.from()? as usize][mv.to() as usize],
+        ))
     }
 
     pub fn get_counter(&self, continuation: &Option<Move>, piece: Role, current: &Move) -> i32 {
         if let Some(continuation) = continuation {
-            self.counter[continuation.role() as usize][continuation.to() as usize][piece as usize]
-                [current.to() as usize]
+            bounded(
+                self.counter[continuation.role() as usize][continuation.to() as usize]
+                    [piece as usize][current.to() as usize],
+            )
         } else {
             0
         }
@@ -38,29 +46,48 @@ impl History {
 
     pub fn get_followup(&self, continuation: &Option<Move>, piece: Role, current: &Move) -> i32 {
         if let Some(continuation) = continuation {
-            self.followup[continuation.role() as usize][continuation.to() as usize][piece as usize]
-                [current.to() as usize]
+            bounded(
+                self.followup[continuation.role() as usize][continuation.to() as usize]
+                    [piece as usize][current.to() as usize],
+            )
         } else {
             0
         }
     }
 
+    /// Halves every score, for a search that builds on the previous one.
+    pub fn age(&mut self) {
+        let capture = self.capture.iter_mut().flatten().flatten().flatten();
+        let main = self.main.iter_mut().flatten().flatten();
+        let counter = self.counter.iter_mut().flatten().flatten().flatten();
+        let followup = self.followup.iter_mut().flatten().flatten().flatten();
+        for v in capture.chain(main).chain(counter).chain(followup) {
+            *v /= 2;
+        }
+    }
+
     pub fn update_capture(
         &mut self,
-        pos: Chess,
+        params: &Parameters,
+        stm: Color,
         mv: &Move,
         fails: &MoveList,
         depth: i32,
     ) -> Option<()> {
-        let turn = pos.turn() as usize;
+        let turn = stm as usize;
         increase(
+            params,
             &mut self.capture[turn][mv.from()? as usize][mv.to() as usize][mv.capture()? as usize],
             depth,
         );
+        // Noisy moves that aren't captures, like quiet promotions and drops, have no entry
         for fail in fails {
+            let (Some(from), Some(captured)) = (fail.from(), fail.capture()) else {
+                continue;
+            };
             decrease(
-                &mut self.capture[turn][fail.from()? as usize][fail.to() as usize]
-                    [fail.capture()? as usize],
+                params,
+                &mut self.capture[turn][from as usize][fail.to() as usize][captured as usize],
                 depth,
             );
         }
@@ -69,17 +96,20 @@ impl History {
 
     pub fn update_main(
         &mut self,
+        params: &Parameters,
         stm: Color,
         mv: &Move,
         fails: &MoveList,
         depth: i32,
     ) -> Option<()> {
         increase(
+            params,
             &mut self.main[stm as usize][mv.from()? as usize][mv.to() as usize],
             depth,
         );
         for fail in fails {
             decrease(
+                params,
                 &mut self.main[stm as usize][fail.from()? as usize][fail.to() as usize],
                 depth,
             );
@@ -89,6 +119,7 @@ impl History {
 
     pub fn update_continuation(
         &mut self,
+        params: &Parameters,
         board: &Board,
         current: &Move,
         fails: &MoveList,
@@ -97,11 +128,12 @@ impl History {
         let piece = current.role();
 
         macro_rules! update_history {
-            ($table:expr, ply: $ply:expr) => {
-                let prev = board.tail_move($ply);
+            ($table:expr, previous: $previous:expr) => {
+                let prev = $previous;
                 if prev != None {
                     if let Some(prev) = prev {
                         increase(
+                            params,
                             &mut $table[prev.role() as usize][prev.to() as usize][piece as usize]
                                 [current.to() as usize],
                             depth,
@@ -109,6 +141,7 @@ impl History {
                         for fail in fails {
                             let piece = fail.role();
                             decrease(
+                                params,
                                 &mut $table[prev.role() as usize][prev.to() as usize]
                                     [piece as usize][fail.to() as usize],
                                 depth,
@@ -118,8 +151,8 @@ impl History {
                 }
             };
         }
-        update_history!(self.counter, ply: 1);
-        update_history!(self.followup, ply: 2);
+        update_history!(self.counter, previous: board.last_move());
+        update_history!(self.followup, previous: board.move_before_last());
     }
 }
 
@@ -134,21 +167,21 @@ impl Default for History {
     }
 }
 
-fn bonus(depth: i32) -> i32 {
-    (history_bonus() * depth + history_bonus_base()).min(history_bonus_max())
+fn bonus(params: &Parameters, depth: i32) -> i32 {
+    (params.history_bonus() * depth + params.history_bonus_base()).min(params.history_bonus_max())
 }
 
-fn malus(depth: i32) -> i32 {
-    (history_malus() * depth + history_malus_base()).min(history_malus_max())
+fn malus(params: &Parameters, depth: i32) -> i32 {
+    (params.history_malus() * depth + params.history_malus_base()).min(params.history_malus_max())
 }
 
-fn increase(v: &mut i32, depth: i32) {
-    let bonus = bonus(depth);
+fn increase(params: &Parameters, v: &mut i32, depth: i32) {
+    let bonus = bonus(params, depth);
     *v += bonus - bonus * *v / MAX_HISTORY;
 }
 
-fn decrease(v: &mut i32, depth: i32) {
-    let malus = malus(depth);
+fn decrease(params: &Parameters, v: &mut i32, depth: i32) {
+    let malus = malus(params, depth);
     *v -= malus + malus * *v / MAX_HISTORY;
 }
 
@@ -162,3 +195,43 @@ fn zeroed_box<T>() -> Box<T> {
         Box::<T>::from_raw(ptr.cast())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::History;
+    use crate::types::parameters::Parameters;
+    use shakmaty::{Color, Move, MoveList, Role, Square};
+
+    #[test]
+    fn test_main_history_per_color() {
+        let params = Parameters::default();
+        let mut history = History::default();
+        let mv = Move::Normal {
+            role: Role::Knight,
+            from: Square::G1,
+            capture: None,
+            to: Square::F3,
+            promotion: None,
+        };
+
+        history.update_main(&params, Color::White, &mv, &MoveList::new(), 5);
+        assert!(history.get_main(Color::White, &mv).unwrap() > 0);
+        assert_eq!(history.get_main(Color::Black, &mv), Some(0));
+
+        // A Black cutoff that penalises the same squares leaves White's score alone
+        let white = history.get_main(Color::White, &mv);
+        let best = Move::Normal {
+            role: Role::Knight,
+            from: Square::B8,
+            capture: None,
+            to: Square::C6,
+            promotion: None,
+        };
+        let mut fails = MoveList::new();
+        fails.push(mv.clone());
+        history.update_main(&params, Color::Black, &best, &fails, 5);
+        assert!(history.get_main(Color::Black, &mv).unwrap() < 0);
+        assert_eq!(history.get_main(Color::White, &mv), white);
+        assert_eq!(history.get_main(Color::White, &best), Some(0));
+    }
+}
@@ -1,16 +1,30 @@
-use super::{defs::SearchRefs, Search};
-use crate::types::parameters::*;
+use super::{
+    defs::{SearchRefs, SearchResult, INFO_INTERVAL},
+    Search,
+};
+use crate::tablebase;
+use crate::transposition::Bound;
 use crate::types::Score;
 use shakmaty::Move;
 
+/// Soft time scale indexed by how many consecutive depths the best move has stayed the same.
+const STABILITY_SCALE: [f64; 5] = [2.50, 1.20, 0.90, 0.80, 0.75];
+
 impl Search {
+    /// Searches a window around the previous score, widening it by `aspiration_growth` whenever
+    /// the score falls outside. A fail high also searches one ply shallower than the previous
+    /// attempt, down to depth 1, since proving the better score is usually cheap. A fail low
+    /// resets that and goes back to the full depth. Every failure counts as a re-search in the
+    /// stats. Mate and tablebase scores move by more than any window between iterations, so
+    /// they get the full window right away.
     pub fn aspiration_search(refs: &mut SearchRefs, mut score: i32, depth: i32) -> i32 {
         refs.board.set_ply(0);
-        if depth <= aspiration_depth() {
+        if depth <= refs.params.aspiration_depth() || Score::is_decisive(score) {
             return Search::alpha_beta(refs, depth, -Score::INFINITY, Score::INFINITY);
         }
 
-        let mut delta = (aspiration_delta() - depth).max(10);
+        let mut delta =
+            (refs.params.aspiration_delta() - depth).max(refs.params.aspiration_min_delta());
         let mut alpha = (score - delta).max(-Score::INFINITY);
         let mut beta = (score + delta).min(Score::INFINITY);
         let mut fail_high_count = 0;
@@ -23,61 +37,514 @@ impl Search {
                 return 0;
             }
 
+            if alpha < score && score < beta {
+                return score;
+            }
+
+            refs.search_info.stats.aspiration_researches += 1;
+
+            // The score is only a bound until the re-search finishes
+            if refs.search_info.elapsed() > INFO_INTERVAL {
+                let bound = if score <= alpha {
+                    Bound::Alpha
+                } else {
+                    Bound::Beta
+                };
+                let pv: Vec<Move> = refs.search_info.root_pv();
+                let line = Search::format_info(refs, depth, score, bound, &pv);
+                refs.report(line);
+            }
+
             if score <= alpha {
                 alpha = (alpha - delta).max(-Score::INFINITY);
                 beta = (alpha + beta) / 2;
                 fail_high_count = 0;
-            } else if score >= beta {
+            } else {
                 beta = (beta + delta).min(Score::INFINITY);
                 fail_high_count += 1;
-            } else {
-                return score;
             }
 
-            delta += delta / 2;
+            delta = (delta as f64 * refs.params.aspiration_growth()) as i32;
         }
     }
 
-    pub fn iterative_deepening(refs: &mut SearchRefs) -> Option<Move> {
+    /// Searches with increasing depth until time runs out or the depth limit is reached.
+    /// The result holds the principal variation of the last completed iteration.
+    pub fn iterative_deepening(refs: &mut SearchRefs) -> SearchResult {
         let mut best_move: Option<Move> = None;
+        let mut pv = Vec::new();
         let mut score = 0;
+        let mut stability = 0;
 
         refs.search_info.start();
+        if refs.search_params.debug {
+            let line = format!(
+                "info string static eval {} raw {} material key {:012x}",
+                refs.board.evaluate(),
+                refs.board.raw_evaluate(),
+                refs.board.material_key()
+            );
+            refs.report(line);
+        }
+        // The tablebase move is played no matter what the search finds. Analysis and pondering
+        // still search on for the GUI, with that move searched first.
+        let tb_root = refs.tablebase.and_then(|tb| tb.probe_root(refs.board));
+        if let Some((mv, wdl)) = &tb_root {
+            refs.search_info.stats.tb_hits += 1;
+            let score = tablebase::score(*wdl, 0);
+            let pv = vec![mv.clone()];
+            let line = Search::format_info(refs, 1, score, Bound::Exact, &pv);
+            refs.report(line);
+            if !refs.search_params.is_open_ended() {
+                return SearchResult {
+                    best_move: Some(mv.clone()),
+                    score,
+                    pv,
+                    depth: 1,
+                    nodes: refs.search_info.total_nodes(),
+                };
+            }
+            let root = refs.board.get_hash();
+            refs.tt
+                .write(root, 0, score, Bound::Nothing, Some(mv.clone()), 0);
+        }
+
         for depth in 1..refs.search_params.depth {
+            let previous_score = score;
             score = Search::aspiration_search(refs, score, depth);
             if refs.search_info.terminated {
+                // Interrupted during the first iteration, fall back to the best root move so far,
+                // or any legal move if none has been searched yet
+                if best_move.is_none() {
+                    best_move = refs
+                        .search_info
+                        .best_root_move
+                        .clone()
+                        .or_else(|| refs.board.legal_moves().first().cloned());
+                    pv = refs.search_info.root_pv();
+                    if pv.first() != best_move.as_ref() {
+                        pv = best_move.iter().cloned().collect();
+                    }
+                }
                 break;
             }
 
             refs.search_info.cp = score;
+            refs.search_info.completed_depth = depth;
 
-            let nodes = refs.search_info.nodes;
-            let elapsed = refs.search_info.elapsed();
-            let nps = if elapsed > 0 {
-                (nodes as f64 / elapsed as f64) * 1000.0
-            } else {
-                0.0
-            };
-            let sel_depth = refs.search_info.sel_depth;
-
-            print!(
-                "info depth {} seldepth {} score cp {} nodes {} nps {:.0} hashfull {} time {} pv ",
-                depth,
-                sel_depth,
-                score,
-                nodes,
-                nps,
-                refs.tt.hashfull(),
-                elapsed
-            );
-            for mv in refs.search_info.pv[0].iter().flatten() {
-                let uci = refs.board.to_uci(mv);
-                print!("{} ", uci);
+            pv = refs.search_info.root_pv();
+            let line = Search::format_info(refs, depth, score, Bound::Exact, &pv);
+            refs.report(line);
+            if refs.search_params.show_san && !pv.is_empty() {
+                refs.report(format!("info string pv {}", refs.board.line_to_san(&pv)));
             }
-            println!();
+            let elapsed = refs.search_info.budget_elapsed();
 
+            if best_move == refs.search_info.pv[0][0] {
+                stability = (stability + 1).min(STABILITY_SCALE.len() - 1);
+            } else {
+                stability = 0;
+            }
             best_move = refs.search_info.pv[0][0].clone();
+
+            refs.check_ponderhit();
+            if let Some(soft_time) = refs
+                .search_params
+                .soft_time
+                .filter(|_| !refs.search_params.ponder)
+            {
+                if depth > 1
+                    && elapsed as f64
+                        > soft_time as f64 * time_scale(stability, previous_score, score)
+                {
+                    break;
+                }
+            }
+        }
+
+        if refs.search_params.debug {
+            refs.report(format!(
+                "info string search limits {} reached depth {} threads 1",
+                refs.search_params.summary(),
+                refs.search_info.completed_depth,
+            ));
+            for line in refs.search_info.stats.lines(refs.search_info.total_nodes()) {
+                refs.report(line);
+            }
+        }
+
+        if let Some((mv, wdl)) = tb_root {
+            if pv.first() != Some(&mv) {
+                pv = vec![mv.clone()];
+            }
+            best_move = Some(mv);
+            refs.search_info.cp = tablebase::score(wdl, 0);
+        }
+        // The game is already drawn if either side claims it. The search still ran for a move
+        // to play in case nobody does, but its score doesn't count.
+        if refs.board.is_claimable_draw() {
+            refs.search_info.cp = Score::DRAW;
+        }
+
+        SearchResult {
+            best_move,
+            score: refs.search_info.cp,
+            pv,
+            depth: refs.search_info.completed_depth,
+            nodes: refs.search_info.total_nodes(),
+        }
+    }
+}
+
+impl Search {
+    /// Formats the UCI `info` line for a search at `depth`. Every line with a score goes
+    /// through here, so that they all convert scores and report progress the same way. `bound`
+    /// marks the score of a failed aspiration window, and an empty PV leaves out `pv`.
+    pub fn format_info(
+        refs: &mut SearchRefs,
+        depth: i32,
+        score: i32,
+        bound: Bound,
+        pv: &[Move],
+    ) -> String {
+        // Same as the result, a claimable draw at the root is reported as a draw
+        let (score, bound) = if refs.board.is_claimable_draw() {
+            (Score::DRAW, Bound::Exact)
+        } else {
+            (score, bound)
+        };
+        let mut line = format!(
+            "info depth {} seldepth {} score {}",
+            depth,
+            refs.search_info.sel_depth,
+            Score::to_uci(score)
+        );
+        match bound {
+            Bound::Alpha => line.push_str(" upperbound"),
+            Bound::Beta => line.push_str(" lowerbound"),
+            _ => (),
+        }
+        if refs.search_params.show_wdl {
+            let (win, draw, loss) = Score::to_wdl(score, refs.board.game_ply());
+            line.push_str(&format!(" wdl {win} {draw} {loss}"));
+        }
+        let elapsed = refs.search_info.elapsed();
+        line.push_str(&format!(
+            " nodes {} nps {} hashfull {} tbhits {} time {}",
+            refs.search_info.total_nodes(),
+            refs.search_info.nps(),
+            refs.tt.hashfull(),
+            refs.search_info.stats.tb_hits,
+            elapsed
+        ));
+        if !pv.is_empty() {
+            line.push_str(" pv");
+            for mv in pv {
+                line.push_str(&format!(" {}", refs.board.to_uci(mv)));
+            }
         }
-        best_move
+        line
+    }
+
+    /// Formats the UCI `info` line announcing the root move about to be searched, counting
+    /// moves from 1.
+    pub fn format_currmove(refs: &SearchRefs, depth: i32, mv: &Move, number: usize) -> String {
+        format!(
+            "info depth {} currmove {} currmovenumber {}",
+            depth,
+            refs.board.to_uci(mv),
+            number
+        )
+    }
+}
+
+/// Scales the soft time bound: spend more time while the best move keeps changing
+/// and when the score drops sharply between iterations.
+fn time_scale(stability: usize, previous_score: i32, score: i32) -> f64 {
+    let score_drop = (previous_score - score).clamp(0, 100) as f64;
+    STABILITY_SCALE[stability] * (1.0 + score_drop / 100.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::board::Board;
+    use crate::search::defs::{SearchInfo, SearchParams, SearchRefs};
+    use crate::search::Search;
+    use crate::transposition::{Bound, TranspositionTable};
+    use crate::types::parameters::Parameters;
+    use crate::types::Score;
+    use std::sync::atomic::AtomicBool;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn test_movetime_returns_move() {
+        let mut board =
+            Board::new("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+                .unwrap();
+        let tt = TranspositionTable::new(1);
+        let mut search_params = SearchParams::from_go("go movetime 1");
+        let mut search_info = SearchInfo::new();
+        let mut refs = SearchRefs::for_test(
+            &mut board,
+            Parameters::default(),
+            &mut search_params,
+            &mut search_info,
+            &tt,
+        );
+        assert!(Search::iterative_deepening(&mut refs).best_move.is_some());
+    }
+
+    #[test]
+    fn test_result_holds_pv() {
+        let mut board = Board::starting_position();
+        let tt = TranspositionTable::new(1);
+        let mut search_params = SearchParams {
+            depth: 5,
+            ..SearchParams::default()
+        };
+        let mut search_info = SearchInfo::new();
+        let mut refs = SearchRefs::for_test(
+            &mut board,
+            Parameters::default(),
+            &mut search_params,
+            &mut search_info,
+            &tt,
+        );
+        let result = Search::iterative_deepening(&mut refs);
+        assert_eq!(result.depth, 4);
+        assert!(result.nodes > 0);
+        assert_eq!(result.pv.first(), result.best_move.as_ref());
+    }
+
+    #[test]
+    fn test_pv_is_searched_line() {
+        let mut board =
+            Board::new("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3").unwrap();
+        let tt = TranspositionTable::new(1);
+        // A shallow upper bound at the root, naming a move the search won't agree with
+        let root = board.get_hash();
+        let decoy = board.legal_moves().last().cloned();
+        tt.write(root, 1, -500, Bound::Alpha, decoy, 0);
+
+        let mut search_params = SearchParams {
+            depth: 5,
+            ..SearchParams::default()
+        };
+        let mut search_info = SearchInfo::new();
+        let mut refs = SearchRefs::for_test(
+            &mut board,
+            Parameters::default(),
+            &mut search_params,
+            &mut search_info,
+            &tt,
+        );
+        let result = Search::iterative_deepening(&mut refs);
+        assert_eq!(result.pv, refs.search_info.root_pv());
+        assert_eq!(result.pv.first(), result.best_move.as_ref());
+        assert!(result.pv.len() <= refs.search_info.sel_depth);
+
+        // Every move is legal where it's played
+        let mut line = refs.board.clone();
+        for mv in &result.pv {
+            assert!(line.legal_moves().contains(mv), "{}", line.fen());
+            line.make_move::<false>(mv);
+        }
+    }
+
+    #[test]
+    fn test_root_pv_stops_at_length() {
+        let moves = Board::starting_position().legal_moves();
+        let mut search_info = SearchInfo::new();
+        search_info.pv[0][0] = Some(moves[0].clone());
+        search_info.pv[0][1] = Some(moves[1].clone());
+        search_info.pv_length[0] = 1;
+        // Left over from a longer line, past the end of this one
+        assert_eq!(search_info.root_pv(), vec![moves[0].clone()]);
+
+        search_info.pv_length[0] = 0;
+        assert!(search_info.root_pv().is_empty());
+    }
+
+    #[test]
+    fn test_seldepth_counts_qsearch() {
+        // Captures and recaptures on d5 continue past the nominal depth in quiescence search
+        let mut board =
+            Board::new("r1bqkb1r/ppp2ppp/2n2n2/3pp3/3PP3/2N2N2/PPP2PPP/R1BQKB1R w KQkq - 0 5")
+                .unwrap();
+        let tt = TranspositionTable::new(1);
+        let mut search_params = SearchParams {
+            depth: 4,
+            ..SearchParams::default()
+        };
+        let mut search_info = SearchInfo::new();
+        assert_eq!(search_info.sel_depth, 0);
+        let mut refs = SearchRefs::for_test(
+            &mut board,
+            Parameters::default(),
+            &mut search_params,
+            &mut search_info,
+            &tt,
+        );
+        let result = Search::iterative_deepening(&mut refs);
+        assert_eq!(result.depth, 3);
+        assert!(search_info.sel_depth > result.depth as usize);
+    }
+
+    #[test]
+    fn test_drawn_root_still_moves() {
+        // Queen up, but the fifty-move rule has already run out
+        let mut board = Board::new("4k3/8/8/8/8/8/8/3QK3 w - - 100 80").unwrap();
+        let tt = TranspositionTable::new(1);
+        let mut search_params = SearchParams {
+            depth: 5,
+            ..SearchParams::default()
+        };
+        let mut search_info = SearchInfo::new();
+        let mut refs = SearchRefs::for_test(
+            &mut board,
+            Parameters::default(),
+            &mut search_params,
+            &mut search_info,
+            &tt,
+        );
+        let result = Search::iterative_deepening(&mut refs);
+        assert!(result.best_move.is_some());
+        assert_eq!(result.pv.first(), result.best_move.as_ref());
+        assert_eq!(result.score, Score::DRAW);
+        // The move comes from a full search, in case the opponent doesn't claim
+        assert_eq!(result.depth, 4);
+    }
+
+    #[test]
+    fn test_finds_shortest_mate() {
+        // Each position has no mate shorter than the one given, but longer ones exist that
+        // mate distance pruning must not let the search prefer. Pruning that depends on the
+        // evaluation is turned off, so the result doesn't depend on the network.
+        let mut params = Parameters::default();
+        for name in ["UCI_NullMove", "UCI_LMR", "UCI_Futility", "UCI_SEEPruning"] {
+            assert!(params.set(name, "false"));
+        }
+        assert!(params.set("razoring_depth", "1"));
+
+        for (fen, moves) in [
+            ("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", 1),
+            // Most queen moves stalemate
+            ("7k/5Q2/6K1/8/8/8/8/8 w - - 0 1", 1),
+            ("7k/8/8/8/8/8/R7/1R4K1 w - - 0 1", 2),
+            ("6k1/8/8/8/8/8/8/1R2R1K1 w - - 0 1", 3),
+            ("8/8/8/8/8/k7/8/K1R1R3 w - - 0 1", 3),
+        ] {
+            let mut board = Board::new(fen).unwrap();
+            let tt = TranspositionTable::new(1);
+            let mut search_params = SearchParams {
+                depth: 10,
+                ..SearchParams::default()
+            };
+            let mut search_info = SearchInfo::new();
+            let mut refs = SearchRefs::for_test(
+                &mut board,
+                params.clone(),
+                &mut search_params,
+                &mut search_info,
+                &tt,
+            );
+            let result = Search::iterative_deepening(&mut refs);
+            assert_eq!(result.score, Score::MATE - (2 * moves - 1), "{fen}");
+        }
+    }
+
+    #[test]
+    fn test_time_ignored_until_root_move_searched() {
+        let mut board = Board::starting_position();
+        let tt = TranspositionTable::new(1);
+        let mut search_params = SearchParams::from_go("go movetime 0");
+        let mut search_info = SearchInfo::new();
+        let mut refs = SearchRefs::for_test(
+            &mut board,
+            Parameters::default(),
+            &mut search_params,
+            &mut search_info,
+            &tt,
+        );
+        refs.search_info.start();
+        thread::sleep(Duration::from_millis(2));
+
+        assert!(!refs.check_time());
+        refs.search_info.root_move_searched = true;
+        assert!(refs.check_time());
+    }
+
+    #[test]
+    fn test_format_info() {
+        let mut board = Board::starting_position();
+        let pv: Vec<_> = ["e2e4", "e7e5"]
+            .map(|uci| {
+                let mv = board
+                    .legal_moves()
+                    .into_iter()
+                    .find(|mv| board.to_uci(mv).to_string() == uci)
+                    .unwrap();
+                board.make_move::<false>(&mv);
+                mv
+            })
+            .into();
+        board.undo_move();
+        board.undo_move();
+
+        let tt = TranspositionTable::new(1);
+        let mut search_params = SearchParams::default();
+        let mut search_info = SearchInfo::new();
+        search_info.sel_depth = 7;
+        let mut refs = SearchRefs::for_test(
+            &mut board,
+            Parameters::default(),
+            &mut search_params,
+            &mut search_info,
+            &tt,
+        );
+        let progress = "nodes 0 nps 0 hashfull 0 tbhits 0 time 0";
+
+        assert_eq!(
+            Search::format_info(&mut refs, 5, 31, Bound::Exact, &pv),
+            format!("info depth 5 seldepth 7 score cp 31 {progress} pv e2e4 e7e5")
+        );
+        assert_eq!(
+            Search::format_info(&mut refs, 5, Score::MATE - 3, Bound::Beta, &pv),
+            format!("info depth 5 seldepth 7 score mate 2 lowerbound {progress} pv e2e4 e7e5")
+        );
+        assert_eq!(
+            Search::format_info(&mut refs, 1, Score::mated_in(2), Bound::Alpha, &[]),
+            format!("info depth 1 seldepth 7 score mate -1 upperbound {progress}")
+        );
+        assert_eq!(
+            Search::format_currmove(&refs, 5, &pv[0], 3),
+            "info depth 5 currmove e2e4 currmovenumber 3"
+        );
+    }
+
+    #[test]
+    fn test_stop_in_first_iteration_returns_move() {
+        let mut board =
+            Board::new("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+                .unwrap();
+        let tt = TranspositionTable::new(1);
+        let mut search_params = SearchParams::from_go("go infinite");
+        let mut search_info = SearchInfo::new();
+        search_info.check_interval = 1;
+        // Stopped before the search even starts, so it ends right after the first root move
+        let stop = AtomicBool::new(true);
+        let mut refs = SearchRefs::for_test(
+            &mut board,
+            Parameters::default(),
+            &mut search_params,
+            &mut search_info,
+            &tt,
+        );
+        refs.stop = &stop;
+        let result = Search::iterative_deepening(&mut refs);
+        assert_eq!(result.depth, 0);
+        let best_move = result.best_move.unwrap();
+        assert!(refs.board.legal_moves().contains(&best_move));
+        assert_eq!(result.pv.first(), Some(&best_move));
     }
 }
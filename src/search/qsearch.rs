@@ -1,24 +1,24 @@
-use super::{defs::SearchRefs, sorting::see, Search};
-use crate::transposition::Bound;
+use super::{defs::SearchRefs, sorting::CapturePicker, Search};
+use crate::transposition::{Bound, QSEARCH_DEPTH};
 use crate::types::MAX_PLY;
 use shakmaty::Move;
 
 impl Search {
     pub fn qsearch(refs: &mut SearchRefs, mut alpha: i32, beta: i32) -> i32 {
-        if (refs.search_info.nodes & 2047) == 0
-            && refs.search_info.elapsed() > refs.search_params.search_time
-        {
-            refs.search_info.terminated = true;
+        if refs.check_time() {
             return 0;
         }
         let ply = refs.board.ply() as usize;
         refs.search_info.nodes += 1;
+        refs.search_info.stats.qsearch_nodes += 1;
         refs.search_info.sel_depth = refs.search_info.sel_depth.max(ply);
 
         let mut tt_move: Option<Move> = None;
-        let hit = refs.tt.read(refs.board.get_hash(), ply);
+        let hit = refs.probe_tt(refs.board.get_hash());
+        refs.search_info.stats.tt_probes += 1;
         if let Some(hit) = hit {
-            if hit.valid_cutoff(alpha, beta, 0) {
+            refs.search_info.stats.tt_hits += 1;
+            if hit.valid_cutoff(alpha, beta, QSEARCH_DEPTH) {
                 return hit.score;
             }
             tt_move = hit.mv;
@@ -33,18 +33,15 @@ impl Search {
         }
 
         let in_check = refs.board.in_check();
-        let mut best_move: Option<&Move> = None;
+        let mut best_move: Option<Move> = None;
         let mut best_score = eval;
 
-        let mut moves = refs.board.capture_moves();
-        Search::sort_moves(&mut moves, &None, &tt_move, refs);
-
-        for mv in &moves {
-            if !in_check && !see(&refs.board.state(), mv, 0).expect("Error evaluating SEE") {
+        for (mv, good_capture) in CapturePicker::new(refs, &tt_move) {
+            if !in_check && !good_capture {
                 continue;
             }
 
-            refs.board.make_move::<false>(mv);
+            refs.board.make_move::<false>(&mv);
             refs.tt.prefetch(refs.board.get_hash());
             let score = -Search::qsearch(refs, -beta, -alpha);
             refs.board.undo_move();
@@ -69,13 +66,65 @@ impl Search {
 
         refs.tt.write(
             refs.board.get_hash(),
-            0,
+            QSEARCH_DEPTH,
             best_score,
             bound,
-            best_move.cloned(),
+            best_move,
             ply,
         );
 
         best_score
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::board::Board;
+    use crate::search::defs::{SearchInfo, SearchParams, SearchRefs};
+    use crate::search::Search;
+    use crate::transposition::TranspositionTable;
+    use crate::types::parameters::Parameters;
+    use crate::types::Score;
+
+    /// Searches `fen` to `depth`, with depth 0 going straight to quiescence. Returns the
+    /// nodes, quiescence nodes and seldepth.
+    fn count(fen: &str, depth: i32) -> (usize, usize, usize) {
+        let mut board = Board::new(fen).unwrap();
+        let tt = TranspositionTable::new(1);
+        let mut search_params = SearchParams::default();
+        let mut search_info = SearchInfo::new();
+        let mut refs = SearchRefs::for_test(
+            &mut board,
+            Parameters::default(),
+            &mut search_params,
+            &mut search_info,
+            &tt,
+        );
+        if depth > 0 {
+            Search::alpha_beta(&mut refs, depth, -Score::INFINITY, Score::INFINITY);
+        } else {
+            Search::qsearch(&mut refs, -Score::INFINITY, Score::INFINITY);
+        }
+        (
+            search_info.nodes,
+            search_info.stats.qsearch_nodes,
+            search_info.sel_depth,
+        )
+    }
+
+    #[test]
+    fn test_qsearch_nodes_counted() {
+        // No captures for either side, so each search of the three king moves is a single
+        // quiescence node. A re-search of a move counts again.
+        let quiet = "k7/8/8/3p4/3P4/8/8/7K w - - 0 1";
+        assert_eq!(count(quiet, 0), (1, 1, 0));
+        let (nodes, qsearch_nodes, sel_depth) = count(quiet, 1);
+        assert!(qsearch_nodes >= 3);
+        assert_eq!(nodes, qsearch_nodes + 1);
+        assert_eq!(sel_depth, 1);
+
+        // The capture on e4 is a node of its own, a ply past the root
+        let capture = "k7/8/8/3p4/4P3/8/8/7K b - - 0 1";
+        assert_eq!(count(capture, 0), (2, 2, 1));
+    }
+}
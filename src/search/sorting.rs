@@ -1,9 +1,9 @@
 use super::defs::SearchRefs;
 use super::{Move, Search};
-use crate::types::parameters::*;
+use crate::types::SEE_VALUES;
 use shakmaty::{
-    attacks::{bishop_attacks, rook_attacks},
-    Bitboard, Board, Chess, Color, MoveList, Position, Role,
+    attacks::{self, bishop_attacks, rook_attacks},
+    Bitboard, Board, Color, MoveList, Role, Square,
 };
 
 const BAD_CAPTURE: i32 = -200_000_000;
@@ -11,8 +11,10 @@ const GOOD_CAPTURE: i32 = 200_000_000;
 const KILLER_BONUS: i32 = 100_000_000;
 const HASH_MOVE: i32 = 300_000_000;
 const DROP_MOVE: i32 = 100_000;
-
-pub const SEE_VALUES: [i32; 7] = [0, 100, 400, 400, 650, 1200, 0];
+const CHECK_BONUS: i32 = 1_000_000;
+/// Quiet moves that take a piece attacked by the opponent's last move to a safe square. Worth
+/// about as much as a well established history score, so it can't outweigh the history terms.
+const THREAT_ESCAPE: i32 = 400_000;
 
 pub fn least_valuable_attacker(board: &Board, attackers: Bitboard) -> Option<Role> {
     Role::ALL
@@ -32,7 +34,10 @@ pub fn move_value(mv: &Move) -> i32 {
     }
 }
 
-pub fn see(pos: &Chess, mv: &Move, threshold: i32) -> Option<bool> {
+/// Static exchange evaluation: whether the exchange started by `mv` wins at least `threshold`.
+/// Only needs the piece placement and the side to move, so callers can lend the board
+/// instead of copying the position.
+pub fn see(board: &Board, turn: Color, mv: &Move, threshold: i32) -> Option<bool> {
     if matches!(mv, Move::Put { .. }) {
         return Some(true);
     }
@@ -40,7 +45,6 @@ pub fn see(pos: &Chess, mv: &Move, threshold: i32) -> Option<bool> {
         return Some(true);
     }
 
-    let board = pos.board();
     let mut balance = move_value(mv) - threshold;
     if balance < 0 {
         return Some(false);
@@ -55,7 +59,7 @@ pub fn see(pos: &Chess, mv: &Move, threshold: i32) -> Option<bool> {
     let _ = occupied.remove(mv.from()?);
     occupied.set(mv.to(), true);
 
-    let mut stm = pos.turn().other();
+    let mut stm = turn.other();
     let mut attackers = (board.attacks_to(mv.to(), Color::White, occupied)
         | board.attacks_to(mv.to(), Color::Black, occupied))
         & occupied;
@@ -91,7 +95,81 @@ pub fn see(pos: &Chess, mv: &Move, threshold: i32) -> Option<bool> {
         attackers &= occupied;
     }
 
-    Some(stm != pos.turn())
+    Some(stm != turn)
+}
+
+/// Static exchange balance of `color` capturing on `square`: the material it wins by starting
+/// with its least valuable attacker, where either side stops capturing once that pays better.
+/// Zero if `color` has no attacker, or no opposing piece stands there to capture.
+pub fn see_square(board: &Board, square: Square, color: Color) -> i32 {
+    let Some(target) = board
+        .role_at(square)
+        .filter(|_| board.by_color(!color).contains(square))
+    else {
+        return 0;
+    };
+
+    let mut occupied = board.occupied();
+    let mut attackers = (board.attacks_to(square, Color::White, occupied)
+        | board.attacks_to(square, Color::Black, occupied))
+        & occupied;
+    let diagonal = board.bishops() | board.queens();
+    let orthogonal = board.rooks() | board.queens();
+
+    // What each capture in the sequence takes, for the side making it
+    let mut gains = [0; 32];
+    let mut captures = 0;
+    let mut on_square = SEE_VALUES[target as usize];
+    let mut stm = color;
+    while captures < gains.len() {
+        let our_attackers = attackers & board.by_color(stm);
+        let Some(attacker) = least_valuable_attacker(board, our_attackers) else {
+            break;
+        };
+        if attacker == Role::King && (attackers & board.by_color(stm.other())).any() {
+            break;
+        }
+
+        gains[captures] = on_square;
+        captures += 1;
+        on_square = SEE_VALUES[attacker as usize];
+        occupied ^= (board.by_role(attacker) & our_attackers).isolate_first();
+        stm = stm.other();
+
+        if [Role::Pawn, Role::Bishop, Role::Queen].contains(&attacker) {
+            attackers |= bishop_attacks(square, occupied) & diagonal;
+        }
+        if [Role::Rook, Role::Queen].contains(&attacker) {
+            attackers |= rook_attacks(square, occupied) & orthogonal;
+        }
+        attackers &= occupied;
+    }
+
+    // From the last capture back, each side only captures if it comes out ahead
+    gains[..captures]
+        .iter()
+        .rev()
+        .fold(0, |reply, &gain| (gain - reply).max(0))
+}
+
+/// Ordering score of a move, kept in its parts so that `moveorder` can show where it comes from.
+#[derive(Default)]
+struct MoveScore {
+    kind: &'static str,
+    /// Fixed offset of the move's kind, plus MVV for captures.
+    base: i32,
+    check: i32,
+    threat: i32,
+    /// Capture history for captures, weighted main history for quiets.
+    history: i32,
+    counter: i32,
+    followup: i32,
+}
+
+impl MoveScore {
+    fn total(&self) -> i32 {
+        self.base + self.check + self.threat + self.history + self.counter + self.followup
+    }
 }
 
 impl Search {
@@ -101,73 +179,210 @@ impl Search {
         tt_move: &Option<Move>,
         refs: &SearchRefs,
     ) {
-        moves.sort_by_key(|m| {
+        let threatened = threatened_pieces(refs);
+        // Scoring looks for checks and attacked squares, so each move is scored only once
+        // rather than on every comparison
+        moves.sort_by_cached_key(|m| {
             if let Some(mv) = &pv_move {
                 if mv == m {}
             }
-            if let Some(mv) = &tt_move {
-                if mv == m {
-                    return HASH_MOVE;
-                }
-            }
+            score_move(refs, m, tt_move, threatened).total()
+        });
+        moves.reverse();
+    }
 
-            if m.is_capture() {
-                let captured = match m.capture() {
-                    Some(role) => role as usize,
-                    None => 0,
-                };
-                let see_value = see(&refs.board.state(), m, 0).expect("Error calculating SEE");
-                let history = refs
-                    .search_info
-                    .history
-                    .get_capture(refs.board.turn(), &m)
-                    .expect("Expected move to be a capture");
-                let mvv = 32 * SEE_VALUES[captured];
-                if !see_value {
-                    return BAD_CAPTURE + history + mvv;
-                }
-                return GOOD_CAPTURE + history + mvv;
-            }
-            let ply = refs.board.ply() as usize;
-            if let Some(killer) = &refs.search_info.killers[ply] {
-                if m == killer {
-                    return KILLER_BONUS;
+    /// Describes the root moves as `sort_moves` orders them, with the terms of each score, as
+    /// UCI `info string` lines.
+    pub fn move_order(refs: &SearchRefs) -> Vec<String> {
+        let tt_move = refs.probe_tt(refs.board.get_hash()).and_then(|hit| hit.mv);
+        let mut moves = refs.board.legal_moves();
+        Search::sort_moves(&mut moves, &None, &tt_move, refs);
+
+        let threatened = threatened_pieces(refs);
+        let mut lines = Vec::new();
+        for (index, mv) in moves.iter().enumerate() {
+            let score = score_move(refs, mv, &tt_move, threatened);
+            lines.push(format!(
+                "info string {} {} {} score {} base {} check {} threat {} history {} counter {} followup {}",
+                index + 1,
+                refs.board.to_uci(mv),
+                score.kind,
+                score.total(),
+                score.base,
+                score.check,
+                score.threat,
+                score.history,
+                score.counter,
+                score.followup
+            ));
+        }
+        lines
+    }
+}
+
+/// Our pieces that the opponent's last move attacks.
+fn threatened_pieces(refs: &SearchRefs) -> Bitboard {
+    let us = refs.board.turn();
+    refs.board.last_move().map_or(Bitboard::EMPTY, |prev| {
+        let board = refs.board.board();
+        board.piece_at(prev.to()).map_or(Bitboard::EMPTY, |piece| {
+            attacks::attacks(prev.to(), piece, board.occupied()) & board.by_color(us)
+        })
+    })
+}
+
+fn score_move(
+    refs: &SearchRefs,
+    m: &Move,
+    tt_move: &Option<Move>,
+    threatened: Bitboard,
+) -> MoveScore {
+    if let Some(mv) = &tt_move {
+        if mv == m {
+            return MoveScore {
+                kind: "hash",
+                base: HASH_MOVE,
+                ..MoveScore::default()
+            };
+        }
+    }
+
+    if m.is_capture() {
+        return capture_score(refs, m).0;
+    }
+    let ply = refs.board.ply() as usize;
+    if let Some(killer) = &refs.search_info.killers[ply] {
+        if m == killer {
+            return MoveScore {
+                kind: "killer",
+                base: KILLER_BONUS,
+                ..MoveScore::default()
+            };
+        }
+    }
+
+    let check = if refs.board.gives_check(m) {
+        CHECK_BONUS
+    } else {
+        0
+    };
+
+    if matches!(m, Move::Put { .. }) {
+        return MoveScore {
+            kind: "drop",
+            base: DROP_MOVE,
+            check,
+            ..MoveScore::default()
+        };
+    }
+
+    let us = refs.board.turn();
+    let escapes_threat = m.role() != Role::King
+        && m.from().is_some_and(|from| threatened.contains(from))
+        && refs.board.attackers_to(m.to(), !us).is_empty();
+
+    let piece = m.role();
+    let continuations = [refs.board.last_move(), refs.board.move_before_last()];
+    let history = &refs.search_info.history;
+    MoveScore {
+        kind: "quiet",
+        base: 0,
+        check,
+        threat: if escapes_threat { THREAT_ESCAPE } else { 0 },
+        history: refs.params.ordering_main()
+            * history.get_main(us, m).expect("Error getting FROM square"),
+        counter: refs.params.ordering_counter() * history.get_counter(&continuations[0], piece, m),
+        followup: refs.params.ordering_followup()
+            * history.get_followup(&continuations[1], piece, m),
+    }
+}
+
+/// Scores a capture by MVV and capture history, split into winning and losing captures by SEE.
+/// Also returns whether the capture passed SEE.
+fn capture_score(refs: &SearchRefs, m: &Move) -> (MoveScore, bool) {
+    let captured = match m.capture() {
+        Some(role) => role as usize,
+        None => 0,
+    };
+    let see_value =
+        see(refs.board.board(), refs.board.turn(), m, 0).expect("Error calculating SEE");
+    let history = refs
+        .search_info
+        .history
+        .get_capture(refs.board.turn(), m)
+        .expect("Expected move to be a capture");
+    let mvv = 32 * SEE_VALUES[captured];
+    let (kind, base) = if see_value {
+        ("good capture", GOOD_CAPTURE)
+    } else {
+        ("bad capture", BAD_CAPTURE)
+    };
+    let score = MoveScore {
+        kind,
+        base: base + mvv,
+        history,
+        ..MoveScore::default()
+    };
+    (score, see_value)
+}
+
+/// Yields the captures of a position best first. Each capture is scored once up front and the
+/// next best is selected lazily, so a cutoff skips sorting the rest of the list.
+pub struct CapturePicker {
+    moves: MoveList,
+    scores: Vec<(i32, bool)>,
+    index: usize,
+}
+
+impl CapturePicker {
+    pub fn new(refs: &SearchRefs, tt_move: &Option<Move>) -> Self {
+        let moves = refs.board.capture_moves();
+        let scores = moves
+            .iter()
+            .map(|m| {
+                let (score, good) = capture_score(refs, m);
+                if tt_move.as_ref() == Some(m) {
+                    (HASH_MOVE, good)
+                } else {
+                    (score.total(), good)
                 }
-            }
+            })
+            .collect();
+        Self {
+            moves,
+            scores,
+            index: 0,
+        }
+    }
+}
 
-            if matches!(m, Move::Put { .. }) {
-                return DROP_MOVE;
-            }
+impl Iterator for CapturePicker {
+    /// The capture and whether it passed SEE.
+    type Item = (Move, bool);
 
-            let piece = m.role();
-            let continuations = [1, 2].map(|ply| refs.board.tail_move(ply));
-            ordering_main()
-                * refs
-                    .search_info
-                    .history
-                    .get_main(refs.board.turn(), m)
-                    .expect("Error getting FROM square")
-                + ordering_counter()
-                    * refs
-                        .search_info
-                        .history
-                        .get_counter(&continuations[0], piece, m)
-                + ordering_followup()
-                    * refs
-                        .search_info
-                        .history
-                        .get_followup(&continuations[1], piece, m)
-        });
-        moves.reverse();
+    fn next(&mut self) -> Option<Self::Item> {
+        let best = (self.index..self.moves.len()).max_by_key(|&i| self.scores[i].0)?;
+        self.moves.swap(self.index, best);
+        self.scores.swap(self.index, best);
+        self.index += 1;
+        Some((
+            self.moves[self.index - 1].clone(),
+            self.scores[self.index - 1].1,
+        ))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::search::sorting::see;
+    use crate::board::Board;
+    use crate::search::defs::{SearchInfo, SearchParams, SearchRefs, SearchStats};
+    use crate::search::sorting::{score_move, see};
+    use crate::search::Search;
+    use crate::transposition::TranspositionTable;
+    use crate::types::parameters::Parameters;
     use shakmaty::fen::Fen;
     use shakmaty::uci::UciMove;
-    use shakmaty::{CastlingMode, Chess};
+    use shakmaty::{Bitboard, CastlingMode, Chess, Position, Role, Square};
     #[test]
     fn test_see1() {
         let fen_string = String::from("1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - ");
@@ -177,7 +392,7 @@ mod tests {
         let uci: UciMove = "d3e5".parse().unwrap();
         let mv = uci.to_move(&pos).unwrap();
 
-        assert_eq!(see(&pos, &mv, -300), Some(true));
+        assert_eq!(see(pos.board(), pos.turn(), &mv, -300), Some(true));
     }
     #[test]
     fn test_see2() {
@@ -188,6 +403,103 @@ mod tests {
         let uci: UciMove = "e1e5".parse().unwrap();
         let mv = uci.to_move(&pos).unwrap();
 
-        assert_eq!(see(&pos, &mv, 100), Some(false));
+        assert_eq!(see(pos.board(), pos.turn(), &mv, 100), Some(false));
+    }
+
+    #[test]
+    fn test_first_move_cutoff_rate() {
+        // Winning captures, quiet middlegames where killers and history carry the ordering,
+        // and an endgame of mostly quiet king and pawn moves
+        let mut stats = SearchStats::default();
+        for fen in [
+            "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4",
+            "r2q1rk1/pp2bppp/2n1pn2/3p4/2PP4/2N1PN2/PP3PPP/R2QKB1R w KQ - 0 9",
+            "rnbqkb1r/pp3ppp/4pn2/2pp4/3P1B2/4PN2/PPP2PPP/RN1QKB1R w KQkq - 0 5",
+            "r1b1k2r/ppq2ppp/2n1pn2/3p4/1bPP4/2N1PN2/PP1B1PPP/R2QKB1R w KQkq - 3 8",
+            "8/5pk1/6p1/3R4/8/6P1/5PK1/2r5 w - - 0 40",
+            "8/8/4k3/3p4/3P1K2/8/8/8 w - - 0 1",
+        ] {
+            let mut board = Board::new(fen).unwrap();
+            let tt = TranspositionTable::new(1);
+            let mut search_params = SearchParams {
+                depth: 5,
+                ..SearchParams::default()
+            };
+            let mut search_info = SearchInfo::new();
+            let mut refs = SearchRefs::for_test(
+                &mut board,
+                Parameters::default(),
+                &mut search_params,
+                &mut search_info,
+                &tt,
+            );
+            Search::iterative_deepening(&mut refs);
+            stats.beta_cutoffs += refs.search_info.stats.beta_cutoffs;
+            stats.first_move_cutoffs += refs.search_info.stats.first_move_cutoffs;
+        }
+        // Pooled over all positions, so that no single small search decides it
+        let rate = stats.first_move_cutoff_rate();
+        assert!(rate >= 0.8, "first move cutoffs {:.1}%", 100.0 * rate);
+    }
+
+    #[test]
+    fn test_threat_escape_ordering() {
+        // The pawn push attacks the knight, so its retreats to safe squares come first
+        let mut board = Board::new("4k3/4p3/8/3N4/8/8/8/4K3 b - - 0 1").unwrap();
+        board.play_uci("e7e6");
+        let tt = TranspositionTable::new(1);
+        let mut search_params = SearchParams::default();
+        let mut search_info = SearchInfo::new();
+        let refs = SearchRefs::for_test(
+            &mut board,
+            Parameters::default(),
+            &mut search_params,
+            &mut search_info,
+            &tt,
+        );
+
+        let mut moves = refs.board.legal_moves();
+        Search::sort_moves(&mut moves, &None, &None, &refs);
+        // b4, b6, c3, c7, e3, f4 and f6. The king covers e7.
+        for mv in &moves[..7] {
+            assert_eq!(mv.role(), Role::Knight);
+            assert_ne!(mv.to(), Square::E7);
+        }
+    }
+
+    #[test]
+    fn test_move_scores_follow_order() {
+        let mut board = Board::new("4k3/8/8/3p4/4P3/8/8/4K3 w - - 0 1").unwrap();
+        let tt = TranspositionTable::new(1);
+        let mut search_params = SearchParams::default();
+        let mut search_info = SearchInfo::new();
+        let moves = board.legal_moves();
+        let find = |uci: &str| {
+            moves
+                .iter()
+                .find(|mv| board.to_uci(mv).to_string() == uci)
+                .cloned()
+        };
+        let (tt_move, killer) = (find("e1d1"), find("e1f1"));
+        search_info.killers[0] = killer.clone();
+        let refs = SearchRefs::for_test(
+            &mut board,
+            Parameters::default(),
+            &mut search_params,
+            &mut search_info,
+            &tt,
+        );
+
+        let mut moves = refs.board.legal_moves();
+        Search::sort_moves(&mut moves, &None, &tt_move, &refs);
+        let scores: Vec<_> = moves
+            .iter()
+            .map(|mv| score_move(&refs, mv, &tt_move, Bitboard::EMPTY))
+            .collect();
+        let kinds: Vec<_> = scores.iter().map(|score| score.kind).collect();
+        assert_eq!(kinds[..3], ["hash", "good capture", "killer"]);
+        assert_eq!(moves[1].to(), Square::D5);
+        assert_eq!(Some(&moves[2]), killer.as_ref());
+        assert!(scores.windows(2).all(|w| w[0].total() >= w[1].total()));
     }
 }
@@ -0,0 +1,89 @@
+use crate::board::Board;
+use crate::types::Score;
+
+use shakmaty::{Chess, Move, Position};
+use shakmaty_syzygy::{Tablebase, Wdl};
+use std::io;
+
+/// Separator between directories in the `SyzygyPath` option.
+#[cfg(windows)]
+const PATH_SEPARATOR: char = ';';
+#[cfg(not(windows))]
+const PATH_SEPARATOR: char = ':';
+
+/// Syzygy tablebases for standard chess.
+pub struct Tablebases {
+    tables: Tablebase<Chess>,
+}
+
+impl Tablebases {
+    /// Loads the tables from one or more directories. Fails if none of them contain any tables.
+    pub fn load(path: &str) -> io::Result<Self> {
+        let mut tables = Tablebase::new();
+        let mut found = 0;
+        for directory in path.split(PATH_SEPARATOR).filter(|dir| !dir.is_empty()) {
+            found += tables.add_directory(directory)?;
+        }
+        if found == 0 {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no tables found"));
+        }
+        Ok(Self { tables })
+    }
+
+    pub fn max_pieces(&self) -> usize {
+        self.tables.max_pieces()
+    }
+
+    /// Probes the win/draw/loss value of the position. Only positions right after a zeroing
+    /// move without castling rights are probed, since the tables know nothing about either.
+    pub fn probe_wdl(&self, board: &Board) -> Option<Wdl> {
+        let pos = board.chess()?;
+        if pos.halfmoves() != 0
+            || pos.castles().any()
+            || board.piece_count() as usize > self.max_pieces()
+        {
+            return None;
+        }
+        self.tables.probe_wdl_after_zeroing(pos).ok()
+    }
+
+    /// Picks the move that preserves the tablebase result at the root, using DTZ to make progress,
+    /// along with that result. The halfmove clock counts here, and a result that DTZ rounding
+    /// leaves open is taken as reached by the tablebase line, which the move follows.
+    pub fn probe_root(&self, board: &Board) -> Option<(Move, Wdl)> {
+        let pos = board.chess()?;
+        if pos.castles().any() || board.piece_count() as usize > self.max_pieces() {
+            return None;
+        }
+        let wdl = self.tables.probe_wdl(pos).ok()?.after_zeroing();
+        let (mv, _) = self.tables.best_move(pos).ok()??;
+        Some((mv, wdl))
+    }
+}
+
+/// Scores a tablebase result at `ply`. Cursed wins and blessed losses are drawn under the
+/// 50-move rule.
+pub fn score(wdl: Wdl, ply: usize) -> i32 {
+    match wdl {
+        Wdl::Win => Score::tb_win_in(ply),
+        Wdl::Loss => -Score::tb_win_in(ply),
+        _ => Score::DRAW,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::score;
+    use crate::types::Score;
+    use shakmaty_syzygy::Wdl;
+
+    #[test]
+    fn test_score() {
+        assert_eq!(score(Wdl::Win, 0), Score::TB_WIN);
+        assert_eq!(score(Wdl::Loss, 3), -Score::tb_win_in(3));
+        assert!(Score::is_win(score(Wdl::Win, 5)) && !Score::is_mate(score(Wdl::Win, 5)));
+        for wdl in [Wdl::CursedWin, Wdl::Draw, Wdl::BlessedLoss] {
+            assert_eq!(score(wdl, 2), Score::DRAW);
+        }
+    }
+}
@@ -1,10 +1,22 @@
 use crate::types::Score;
 
-use shakmaty::Move;
+use shakmaty::{Move, Role, Square};
+use std::sync::atomic::{AtomicU64, Ordering};
 pub const DEFAULT_TT_SIZE: usize = 512;
 
+/// Depth stored with quiescence search results. It's below any main search depth, so those
+/// entries never cut off a main search node, while qsearch can use entries of any depth.
+pub const QSEARCH_DEPTH: i32 = -1;
+
 const MEGABYTE: usize = 1024 * 1024;
-const INTERNAL_ENTRY_SIZE: usize = std::mem::size_of::<InternalEntry>();
+const SLOT_SIZE: usize = std::mem::size_of::<Slot>();
+
+// Layout of the data word of a slot, from the low bits up
+const MOVE_BITS: u64 = 24;
+const SCORE_SHIFT: u64 = MOVE_BITS;
+const DEPTH_SHIFT: u64 = SCORE_SHIFT + 16;
+const BOUND_SHIFT: u64 = DEPTH_SHIFT + 8;
+const VALID: u64 = 1 << (BOUND_SHIFT + 2);
 
 #[derive(Clone)]
 pub struct Entry {
@@ -24,6 +36,28 @@ impl Entry {
             Bound::Nothing => false,
         }
     }
+
+    fn pack(&self) -> u64 {
+        pack_move(&self.mv)
+            | u64::from(self.score as i16 as u16) << SCORE_SHIFT
+            | u64::from(self.depth as i8 as u8) << DEPTH_SHIFT
+            | (self.bound as u64) << BOUND_SHIFT
+            | VALID
+    }
+
+    fn unpack(data: u64) -> Self {
+        Self {
+            mv: unpack_move(data & ((1 << MOVE_BITS) - 1)),
+            score: i32::from((data >> SCORE_SHIFT) as u16 as i16),
+            depth: i32::from((data >> DEPTH_SHIFT) as u8 as i8),
+            bound: match (data >> BOUND_SHIFT) & 3 {
+                0 => Bound::Exact,
+                1 => Bound::Alpha,
+                2 => Bound::Beta,
+                _ => Bound::Nothing,
+            },
+        }
+    }
 }
 
 /// Type of the score returned by the search.
@@ -35,85 +69,74 @@ pub enum Bound {
     Nothing,
 }
 
-/// Internal representation of a transposition table entry (8 bytes).
-#[derive(Clone)]
-struct InternalEntry {
-    key: u16,
-    mv: Option<Move>,
-    score: i32,
-    depth: i32,
-    bound: Bound,
-    valid: bool,
+/// A table slot (16 bytes), written and read without locks. The key is the full hash xored
+/// with the data, so a slot whose two words come from different writes fails verification
+/// instead of returning one position's data for another.
+#[derive(Default)]
+struct Slot {
+    key: AtomicU64,
+    data: AtomicU64,
 }
 
-impl Default for InternalEntry {
-    fn default() -> Self {
-        Self {
-            key: 0,
-            mv: None,
-            score: 0,
-            depth: 0,
-            bound: Bound::Nothing,
-            valid: false,
-        }
+impl Slot {
+    /// The data stored for `hash`, if any.
+    fn load(&self, hash: u64) -> Option<u64> {
+        let data = self.data.load(Ordering::Relaxed);
+        let key = self.key.load(Ordering::Relaxed);
+        (data & VALID != 0 && key ^ data == hash).then_some(data)
+    }
+
+    fn store(&self, hash: u64, data: u64) {
+        self.key.store(hash ^ data, Ordering::Relaxed);
+        self.data.store(data, Ordering::Relaxed);
     }
 }
 
-/// The transposition table is used to cache previously performed search results.
+/// The transposition table is used to cache previously performed search results. All access
+/// goes through shared references, so it can be shared between threads without a lock.
 pub struct TranspositionTable {
-    vector: Vec<InternalEntry>,
+    slots: Box<[Slot]>,
 }
 
 impl TranspositionTable {
-    /// Clears the transposition table. This will remove all entries but keep the allocated memory.
-    pub fn clear(&mut self) {
-        self.vector.fill(InternalEntry::default());
+    /// Creates a transposition table of the specified size in megabytes.
+    pub fn new(megabytes: usize) -> Self {
+        Self {
+            slots: (0..megabytes * MEGABYTE / SLOT_SIZE)
+                .map(|_| Slot::default())
+                .collect(),
+        }
     }
 
-    /// Resizes the transposition table to the specified size in megabytes. This will clear all entries.
-    pub fn resize(&mut self, megabytes: usize) {
-        let len = megabytes * MEGABYTE / INTERNAL_ENTRY_SIZE;
-
-        self.vector = Vec::new();
-        self.vector.reserve_exact(len);
-
-        unsafe {
-            self.vector.set_len(len);
+    /// Clears the transposition table. This will remove all entries but keep the allocated memory.
+    pub fn clear(&self) {
+        for slot in self.slots.iter() {
+            slot.key.store(0, Ordering::Relaxed);
+            slot.data.store(0, Ordering::Relaxed);
         }
-        self.clear();
     }
 
     /// Returns the approximate load factor of the transposition table in permille (on a scale of `0` to `1000`).
     pub fn hashfull(&self) -> usize {
-        self.vector
+        self.slots
             .iter()
             .take(1000)
-            .filter(|slot| slot.valid)
+            .filter(|slot| slot.data.load(Ordering::Relaxed) & VALID != 0)
             .count()
     }
 
     pub fn read(&self, hash: u64, ply: usize) -> Option<Entry> {
-        let index = self.index(hash);
-        let entry = self.vector[index].clone();
-        if !entry.valid || entry.key != verification_key(hash) {
-            return None;
-        }
-
-        let mut hit = Entry {
-            mv: entry.mv,
-            depth: entry.depth,
-            score: entry.score,
-            bound: entry.bound,
-        };
+        let data = self.slots[self.index(hash)].load(hash)?;
+        let mut hit = Entry::unpack(data);
         // Adjust mate distance from "plies from the current position" to "plies from the root"
-        if hit.score.abs() > Score::MATE_BOUND {
+        if Score::is_mate(hit.score) {
             hit.score -= hit.score.signum() * ply as i32;
         }
         Some(hit)
     }
 
     pub fn write(
-        &mut self,
+        &self,
         hash: u64,
         depth: i32,
         mut score: i32,
@@ -122,28 +145,28 @@ impl TranspositionTable {
         ply: usize,
     ) {
         // Adjust mate distance from "plies from the root" to "plies from the current position"
-        if score.abs() > Score::MATE_BOUND {
+        if Score::is_mate(score) {
             score += score.signum() * ply as i32;
         }
 
-        let key = verification_key(hash);
-        let index = self.index(hash);
-
-        let entry = self.vector[index].clone();
-        if mv.is_none() && entry.key == key {
-            if let Some(old_m) = entry.mv {
-                mv = Some(old_m);
+        let slot = &self.slots[self.index(hash)];
+        if let Some(old) = slot.load(hash).map(Entry::unpack) {
+            // A quiescence result says less about the position than a main search one
+            if depth == QSEARCH_DEPTH && old.depth > depth {
+                return;
+            }
+            if mv.is_none() {
+                mv = old.mv;
             }
         }
 
-        self.vector[index] = InternalEntry {
-            key,
+        let entry = Entry {
             mv,
-            depth,
             score,
+            depth,
             bound,
-            valid: true,
         };
+        slot.store(hash, entry.pack());
     }
 
     pub fn prefetch(&self, hash: u64) {
@@ -152,7 +175,7 @@ impl TranspositionTable {
             use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
 
             let index = self.index(hash);
-            let ptr = self.vector.as_ptr().add(index).cast();
+            let ptr = self.slots.as_ptr().add(index).cast();
             _mm_prefetch::<_MM_HINT_T0>(ptr);
         }
     }
@@ -160,22 +183,184 @@ impl TranspositionTable {
     fn index(&self, hash: u64) -> usize {
         // Fast hash table index calculation
         // For details, see: https://lemire.me/blog/2016/06/27/a-fast-alternative-to-the-modulo-reduction
-        (((hash as u128) * (self.vector.len() as u128)) >> 64) as usize
+        (((hash as u128) * (self.slots.len() as u128)) >> 64) as usize
     }
 }
 
-/// Returns the verification key of the hash (bottom 16 bits).
-const fn verification_key(hash: u64) -> u16 {
-    hash as u16
+/// Packs a move into the low `MOVE_BITS` of a slot: from and to squares, the moving, captured
+/// and promoted roles, and the kind of move. Zero is no move.
+fn pack_move(mv: &Option<Move>) -> u64 {
+    let role = |role: Option<Role>| role.map_or(0, |role| role as u64);
+    let (kind, from, to, moved, capture, promotion) = match *mv {
+        None => return 0,
+        Some(Move::Normal {
+            role: moved,
+            from,
+            capture,
+            to,
+            promotion,
+        }) => (0, from, to, Some(moved), capture, promotion),
+        Some(Move::EnPassant { from, to }) => (1, from, to, None, None, None),
+        Some(Move::Castle { king, rook }) => (2, king, rook, None, None, None),
+        Some(Move::Put { role: moved, to }) => (3, to, to, Some(moved), None, None),
+    };
+    1 << 23
+        | kind << 21
+        | role(promotion) << 18
+        | role(capture) << 15
+        | role(moved) << 12
+        | (to as u64) << 6
+        | from as u64
+}
+
+fn unpack_move(bits: u64) -> Option<Move> {
+    if bits == 0 {
+        return None;
+    }
+    let role = |shift: u64| {
+        Role::ALL
+            .get(((bits >> shift) & 7).checked_sub(1)? as usize)
+            .copied()
+    };
+    let from = Square::new((bits & 63) as u32);
+    let to = Square::new(((bits >> 6) & 63) as u32);
+    Some(match (bits >> 21) & 3 {
+        0 => Move::Normal {
+            role: role(12)?,
+            from,
+            capture: role(15),
+            to,
+            promotion: role(18),
+        },
+        1 => Move::EnPassant { from, to },
+        2 => Move::Castle {
+            king: from,
+            rook: to,
+        },
+        _ => Move::Put {
+            role: role(12)?,
+            to,
+        },
+    })
 }
 
 impl Default for TranspositionTable {
     fn default() -> Self {
-        Self {
-            vector: vec![
-                InternalEntry::default();
-                DEFAULT_TT_SIZE * MEGABYTE / INTERNAL_ENTRY_SIZE
-            ],
+        Self::new(DEFAULT_TT_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bound, Slot, TranspositionTable, QSEARCH_DEPTH};
+    use crate::types::Score;
+    use shakmaty::{Move, Role, Square};
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_qsearch_entries() {
+        let tt = TranspositionTable::new(1);
+        tt.write(1, QSEARCH_DEPTH, 50, Bound::Exact, None, 0);
+        let hit = tt.read(1, 0).unwrap();
+        assert!(hit.valid_cutoff(0, 100, QSEARCH_DEPTH));
+        assert!(!hit.valid_cutoff(0, 100, 0));
+
+        // Main search results are kept over later quiescence ones
+        tt.write(2, 3, 20, Bound::Beta, None, 0);
+        tt.write(2, QSEARCH_DEPTH, -40, Bound::Alpha, None, 0);
+        let hit = tt.read(2, 0).unwrap();
+        assert_eq!(hit.depth, 3);
+        assert!(hit.valid_cutoff(0, 10, QSEARCH_DEPTH));
+    }
+
+    #[test]
+    fn test_entries_round_trip() {
+        let tt = TranspositionTable::new(1);
+        let moves = [
+            None,
+            Some(Move::Normal {
+                role: Role::Knight,
+                from: Square::G1,
+                capture: None,
+                to: Square::F3,
+                promotion: None,
+            }),
+            Some(Move::Normal {
+                role: Role::Pawn,
+                from: Square::B7,
+                capture: Some(Role::Rook),
+                to: Square::A8,
+                promotion: Some(Role::Queen),
+            }),
+            Some(Move::EnPassant {
+                from: Square::E5,
+                to: Square::D6,
+            }),
+            Some(Move::Castle {
+                king: Square::E8,
+                rook: Square::A8,
+            }),
+            Some(Move::Put {
+                role: Role::Bishop,
+                to: Square::H8,
+            }),
+        ];
+        for (index, mv) in moves.into_iter().enumerate() {
+            for (hash, score, depth, bound) in [
+                (index * 3, Score::MATE - 7, 30, Bound::Exact),
+                (index * 3 + 1, -Score::INFINITY, QSEARCH_DEPTH, Bound::Alpha),
+                (index * 3 + 2, -3, 0, Bound::Beta),
+            ] {
+                let hash = (hash as u64) << 48;
+                tt.write(hash, depth, score, bound, mv.clone(), 0);
+                let hit = tt.read(hash, 0).unwrap();
+                assert_eq!(hit.mv, mv);
+                assert_eq!((hit.score, hit.depth), (score, depth));
+                assert!(hit.bound == bound);
+            }
+        }
+    }
+
+    #[test]
+    fn test_mismatched_slot_is_a_miss() {
+        let slot = Slot::default();
+        assert_eq!(slot.load(0), None);
+
+        slot.store(5, 0x11 | super::VALID);
+        assert_eq!(slot.load(5), Some(0x11 | super::VALID));
+        assert_eq!(slot.load(6), None);
+
+        // Data from another write no longer verifies against the key
+        slot.data.store(0x12 | super::VALID, Ordering::Relaxed);
+        assert_eq!(slot.load(5), None);
+    }
+
+    #[test]
+    fn test_shared_between_threads() {
+        let tt = Arc::new(TranspositionTable::new(1));
+        let writers: Vec<_> = (0..4u64)
+            .map(|thread| {
+                let tt = Arc::clone(&tt);
+                thread::spawn(move || {
+                    for hash in (thread..4000).step_by(4) {
+                        tt.write(hash << 40, 5, hash as i32, Bound::Exact, None, 0);
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
         }
+        // Every hit, from whichever thread, is the entry written for that hash
+        let hits = (0..4000u64)
+            .filter_map(|hash| tt.read(hash << 40, 0).map(|hit| (hash, hit)))
+            .inspect(|(hash, hit)| assert_eq!(hit.score, *hash as i32))
+            .count();
+        assert!(hits > 0);
+
+        tt.clear();
+        assert_eq!(tt.hashfull(), 0);
     }
 }
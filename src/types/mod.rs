@@ -4,3 +4,7 @@ pub mod score;
 pub use score::*;
 
 pub const MAX_PLY: usize = 96;
+pub const MAX_EXTENSIONS: i32 = 16;
+
+/// Piece values indexed by role, used for exchange evaluation and material counting.
+pub const SEE_VALUES: [i32; 7] = [0, 100, 400, 400, 650, 1200, 0];
@@ -1,61 +1,154 @@
-pub const LMR_MOVES_PLAYED: i32 = 3;
-pub const LMR_DEPTH: i32 = 3;
-
 pub const LMP_DEPTH: i32 = 4;
 pub const LMP_MARGIN: i32 = 3;
 
+/// Bound on every history score. The gravity update never leaves `[-MAX_HISTORY, MAX_HISTORY]`
+/// as long as a single bonus stays below it, and move ordering multiplies scores by the
+/// `ordering_*` weights, so those products must fit in an `i32`.
+pub const MAX_HISTORY: i32 = 16384;
+
+#[derive(Clone)]
 pub struct Parameters {
     lmr: [[f64; 64]; 64],
+    tunables: Tunables,
+    toggles: Toggles,
+}
+
+/// Pruning techniques that can be switched off through hidden UCI options, to find out which
+/// one is responsible when the search misses a move.
+#[derive(Clone)]
+struct Toggles {
+    null_move: bool,
+    lmr: bool,
+    futility: bool,
+    see_pruning: bool,
+}
+
+impl Default for Toggles {
+    fn default() -> Self {
+        Self {
+            null_move: true,
+            lmr: true,
+            futility: true,
+            see_pruning: true,
+        }
+    }
 }
 
 impl Parameters {
     pub fn lmr(&self, depth: i32, moves: i32) -> f64 {
         self.lmr[depth.min(63) as usize][moves.min(63) as usize]
     }
-}
 
-impl Default for Parameters {
-    fn default() -> Self {
-        let mut lmr = [[0.0; 64]; 64];
-        for (depth, row) in lmr.iter_mut().enumerate() {
+    pub const fn use_null_move(&self) -> bool {
+        self.toggles.null_move
+    }
+
+    pub const fn use_lmr(&self) -> bool {
+        self.toggles.lmr
+    }
+
+    /// Covers both futility pruning in the move loop and reverse futility pruning.
+    pub const fn use_futility(&self) -> bool {
+        self.toggles.futility
+    }
+
+    pub const fn use_see_pruning(&self) -> bool {
+        self.toggles.see_pruning
+    }
+
+    /// Updates a tunable or pruning toggle by name, as sent through `setoption`. Returns `false`
+    /// if the name is unknown or the value is invalid or out of range.
+    pub fn set(&mut self, name: &str, value: &str) -> bool {
+        let toggle = match name {
+            "UCI_NullMove" => Some(&mut self.toggles.null_move),
+            "UCI_LMR" => Some(&mut self.toggles.lmr),
+            "UCI_Futility" => Some(&mut self.toggles.futility),
+            "UCI_SEEPruning" => Some(&mut self.toggles.see_pruning),
+            _ => None,
+        };
+        if let Some(toggle) = toggle {
+            return match value.parse() {
+                Ok(enabled) => {
+                    *toggle = enabled;
+                    true
+                }
+                Err(_) => false,
+            };
+        }
+
+        if !self.tunables.set(name, value) {
+            return false;
+        }
+        self.init_lmr();
+        true
+    }
+
+    fn init_lmr(&mut self) {
+        let (base, divisor) = (self.lmr_base(), self.lmr_divisor());
+        for (depth, row) in self.lmr.iter_mut().enumerate() {
             for (moves, r) in row.iter_mut().enumerate() {
-                *r = lmr_base() + (depth as f64).ln() * (moves as f64).ln() / lmr_divisor();
+                *r = base + (depth as f64).ln() * (moves as f64).ln() / divisor;
             }
         }
-        Self { lmr }
     }
 }
 
-#[cfg(not(feature = "tuning"))]
-macro_rules! define {
-    ($($type:ident $name:ident: $value:expr, $min:expr, $max:expr; )*) => {
-        $(pub const fn $name() -> $type {
-            $value
-        })*
-    };
+impl Default for Parameters {
+    fn default() -> Self {
+        let mut params = Self {
+            lmr: [[0.0; 64]; 64],
+            tunables: Tunables::default(),
+            toggles: Toggles::default(),
+        };
+        params.init_lmr();
+        params
+    }
 }
 
-#[cfg(feature = "tuning")]
+/// Generates the tunables as fields of `Parameters`, read through accessors of the same name
+/// and changed at runtime through UCI options for SPSA tuning.
 macro_rules! define {
     ($($type:ident $name:ident: $value:expr, $min:expr, $max:expr; )*) => {
-        pub fn set_parameter(name: &str, value: &str) {
-            match name {
-                $(stringify!($name) => unsafe { parameters::$name = value.parse().unwrap() },)*
-                _ => panic!("Unknown tunable parameter: {}", name),
+        #[derive(Clone)]
+        struct Tunables {
+            $($name: $type,)*
+        }
+
+        impl Default for Tunables {
+            fn default() -> Self {
+                Self {
+                    $($name: $value,)*
+                }
             }
         }
 
-        pub fn print_options() {
-            $(println!("option name {} type string default {}", stringify!($name), $value);)*
+        impl Parameters {
+            $(pub const fn $name(&self) -> $type {
+                self.tunables.$name
+            })*
         }
 
-        $(pub fn $name() -> $type {
-            unsafe { parameters::$name }
-        })*
+        impl Tunables {
+            fn set(&mut self, name: &str, value: &str) -> bool {
+                match name {
+                    $(stringify!($name) => match value.parse() {
+                        Ok(value) if (($min as $type)..=($max as $type)).contains(&value) => self.$name = value,
+                        _ => return false,
+                    },)*
+                    _ => return false,
+                }
+                true
+            }
+        }
 
-        #[allow(non_upper_case_globals)]
-        mod parameters {
-            $(pub static mut $name: $type = $value;)*
+        /// Prints every tunable as a UCI option. Integer parameters are spins, while
+        /// fractional ones are sent as strings since UCI has no float option type.
+        #[cfg(feature = "tuning")]
+        pub fn print_options() {
+            $(match stringify!($type) {
+                "i32" => println!("option name {} type spin default {} min {} max {}", stringify!($name), $value, $min, $max),
+                _ => println!("option name {} type string default {}", stringify!($name), $value),
+            })*
         }
     };
 }
@@ -67,22 +160,53 @@ define!(
     i32 razoring_depth: 4, 1, 10;
     i32 razoring_margin: 220, 0, 440;
     i32 razoring_fixed_margin: 135, 0, 270;
+    i32 razoring_verify_margin: 40, 0, 200;
 
     i32 fp_depth: 5, 1, 10;
     i32 fp_margin: 130, 0, 260;
     i32 fp_fixed_margin: 45, 0, 90;
+    i32 fp_history: 128, 64, 256;
 
     i32 search_deeper_margin: 80, 0, 160;
 
     i32 see_depth: 6, 1, 112;
     i32 see_noisy_margin: 100, 50, 150;
     i32 see_quiet_margin: 70, 50, 150;
+    i32 see_history: 64, 16, 256;
 
     i32 iir_depth: 4, 1, 10;
 
+    // Pawn pushes to the seventh rank are extended while the non-pawn material of both sides
+    // together is below this
+    i32 pawn_push_material: 2600, 0, 8200;
+
+    // Null move pruning from this depth on, reduced by base + depth / depth divisor, plus
+    // a ply per eval divisor the evaluation is above beta, up to the eval maximum
+    i32 nmp_depth: 4, 2, 8;
+    i32 nmp_base: 3, 1, 6;
+    i32 nmp_depth_divisor: 3, 1, 6;
+    i32 nmp_eval_divisor: 200, 50, 400;
+    i32 nmp_eval_max: 4, 0, 8;
+
+    // Multi-cut searches the first moves this much shallower, and prunes the node once enough
+    // of them fail high
+    i32 multicut_depth: 8, 4, 16;
+    i32 multicut_reduction: 3, 1, 6;
+    i32 multicut_moves: 6, 2, 12;
+    i32 multicut_cutoffs: 3, 1, 6;
+
+    // Aspiration windows start above this depth, with a half width of delta minus the depth,
+    // but at least the minimum. Each failed search widens them by the growth factor.
     i32 aspiration_depth: 6, 1, 12;
     i32 aspiration_delta: 30, 15, 45;
+    i32 aspiration_min_delta: 10, 5, 20;
+    f64 aspiration_growth: 1.5, 1.1, 3.0;
 
+    // Moves searched at a node before later quiets start getting reduced
+    i32 lmr_moves_played: 3, 1, 8;
+    // Shallowest depth at which quiets are reduced
+    i32 lmr_depth: 3, 1, 8;
+    // Reduction table: base + ln(depth) * ln(moves) / divisor
     f64 lmr_base: 0.73, 0.5, 1.5;
     f64 lmr_divisor: 2.22, 1.5, 3.5;
 
@@ -100,3 +224,79 @@ define!(
     i32 ordering_counter: 40, 0, 160;
     i32 ordering_followup: 40, 0, 160;
 );
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_parameters() {
+        let params = Parameters::default();
+        assert_eq!(params.rfp_depth(), 7);
+        assert_eq!(params.rfp_margin(), 75);
+        assert_eq!(params.razoring_margin(), 220);
+        assert_eq!(params.fp_margin(), 130);
+        assert_eq!(params.see_depth(), 6);
+        assert_eq!(params.iir_depth(), 4);
+        assert_eq!(params.aspiration_min_delta(), 10);
+        assert_eq!(params.aspiration_growth(), 1.5);
+        assert_eq!(params.lmr(1, 1), 0.73);
+        assert_eq!(params.lmr_moves_played(), 3);
+        assert_eq!(params.lmr_depth(), 3);
+    }
+
+    #[test]
+    fn test_history_products_fit() {
+        // The largest weights the options allow, on the largest history scores
+        let mut params = Parameters::default();
+        for name in ["ordering_main", "ordering_counter", "ordering_followup"] {
+            assert!(params.set(name, "160"));
+        }
+        let main = params.ordering_main().checked_mul(MAX_HISTORY).unwrap();
+        let counter = params.ordering_counter().checked_mul(MAX_HISTORY).unwrap();
+        let followup = params.ordering_followup().checked_mul(MAX_HISTORY).unwrap();
+        assert!(main
+            .checked_add(counter)
+            .and_then(|sum| sum.checked_add(followup))
+            .is_some());
+
+        // A single update can't push a score past the bound
+        assert!(params.set("history_bonus_max", "3500"));
+        assert!(params.set("history_malus_max", "3500"));
+        assert!(params.history_bonus_max() < MAX_HISTORY);
+        assert!(params.history_malus_max() < MAX_HISTORY);
+    }
+
+    #[test]
+    fn test_set_parameter() {
+        let mut params = Parameters::default();
+        assert!(params.set("rfp_margin", "90"));
+        assert_eq!(params.rfp_margin(), 90);
+
+        assert!(!params.set("rfp_margin", "1000"));
+        assert!(!params.set("rfp_margin", "abc"));
+        assert!(!params.set("unknown", "1"));
+        assert_eq!(params.rfp_margin(), 90);
+
+        let before = params.lmr(10, 10);
+        assert!(params.set("lmr_base", "1.0"));
+        assert!(params.lmr(10, 10) > before);
+    }
+
+    #[test]
+    fn test_pruning_toggles() {
+        let mut params = Parameters::default();
+        assert!(params.use_null_move() && params.use_lmr());
+        assert!(params.use_futility() && params.use_see_pruning());
+
+        assert!(params.set("UCI_NullMove", "false"));
+        assert!(params.set("UCI_SEEPruning", "false"));
+        assert!(!params.use_null_move() && !params.use_see_pruning());
+        assert!(params.use_lmr() && params.use_futility());
+
+        assert!(!params.set("UCI_LMR", "off"));
+        assert!(params.use_lmr());
+        assert!(params.set("UCI_NullMove", "true"));
+        assert!(params.use_null_move());
+    }
+}
@@ -1,3 +1,19 @@
+use super::MAX_PLY;
+
+/// Spread of the win probability model, in centipawns. Larger values give flatter curves.
+pub const WDL_SCALE: f64 = 90.0;
+/// Score at which the win probability reaches 50%, at the start and by the end of the game.
+/// Advantages convert more reliably with fewer pieces, so the midpoint drops as the game goes on.
+pub const WDL_MIDPOINT: (f64, f64) = (350.0, 200.0);
+/// Game ply by which the midpoint has moved all the way to its endgame value.
+const WDL_MIDPOINT_PLY: f64 = 160.0;
+
+/// Score layout, from the outside in: `INFINITY` > `MATE` > `MATE_BOUND` > `TB_WIN` > `MAX_EVAL`.
+///
+/// Mates are scored in `(MATE_BOUND, MATE]` and tablebase wins in `(MAX_EVAL, TB_WIN]`, counting
+/// down by ply. Static evaluations, with or without pruning margins, never leave
+/// `[-MATE_BOUND, MATE_BOUND]`, and plain evaluations stay within `MAX_EVAL`. The assertions below
+/// keep these relationships true if any of the constants change.
 pub struct Score;
 
 impl Score {
@@ -8,7 +24,181 @@ impl Score {
     pub const MATE: i32 = Self::INFINITY - 1000;
     pub const MATE_BOUND: i32 = Self::MATE - 500;
 
+    /// Tablebase wins are scored below any mate so that a found mate is always preferred.
+    pub const TB_WIN: i32 = Self::MATE_BOUND - 500;
+
+    /// Largest static evaluation, below any tablebase win.
+    pub const MAX_EVAL: i32 = Self::TB_WIN - MAX_PLY as i32 - 1;
+
+    /// A mate, found for either side.
+    pub const fn is_mate(score: i32) -> bool {
+        score > Self::MATE_BOUND || score < -Self::MATE_BOUND
+    }
+
+    /// A mate or tablebase win for the side the score belongs to.
+    pub const fn is_win(score: i32) -> bool {
+        score > Self::MAX_EVAL
+    }
+
+    /// A mate or tablebase loss for the side the score belongs to.
+    pub const fn is_loss(score: i32) -> bool {
+        score < -Self::MAX_EVAL
+    }
+
+    /// A proven result either way, which no evaluation can produce.
+    pub const fn is_decisive(score: i32) -> bool {
+        Self::is_win(score) || Self::is_loss(score)
+    }
+
     pub const fn mated_in(ply: usize) -> i32 {
         -Self::MATE + ply as i32
     }
+
+    pub const fn tb_win_in(ply: usize) -> i32 {
+        Self::TB_WIN - ply as i32
+    }
+
+    /// Formats a score for UCI: `mate` with the distance in moves, negative when getting
+    /// mated, or `cp` for everything else, tablebase wins included.
+    pub fn to_uci(score: i32) -> String {
+        if Self::is_mate(score) && score > 0 {
+            format!("mate {}", (Self::MATE - score + 1) / 2)
+        } else if Self::is_mate(score) {
+            format!("mate {}", -((Self::MATE + score) / 2))
+        } else {
+            format!("cp {score}")
+        }
+    }
+
+    /// Converts a score at the given game ply into per-mille win, draw and loss probabilities
+    /// from a logistic model. Mates and tablebase wins are certain results.
+    pub fn to_wdl(score: i32, ply: usize) -> (u32, u32, u32) {
+        if Self::is_win(score) {
+            return (1000, 0, 0);
+        }
+        if Self::is_loss(score) {
+            return (0, 0, 1000);
+        }
+
+        let progress = (ply as f64 / WDL_MIDPOINT_PLY).min(1.0);
+        let midpoint = WDL_MIDPOINT.0 + (WDL_MIDPOINT.1 - WDL_MIDPOINT.0) * progress;
+        let probability = |score: f64| 1.0 / (1.0 + ((midpoint - score) / WDL_SCALE).exp());
+
+        let win = (1000.0 * probability(score as f64)).round() as u32;
+        let loss = (1000.0 * probability(-score as f64)).round() as u32;
+        (win, 1000 - win - loss, loss)
+    }
+
+    /// Scales a per-depth pruning margin, saturating instead of overflowing.
+    pub const fn margin(per_depth: i32, depth: i32) -> i32 {
+        per_depth.saturating_mul(depth)
+    }
+
+    /// Offsets a static evaluation by a pruning margin. The sum saturates and stays outside
+    /// the mate range, so no margin can turn an evaluation into a phantom mate.
+    pub const fn offset(eval: i32, margin: i32) -> i32 {
+        let score = eval.saturating_add(margin);
+        if score > Self::MATE_BOUND {
+            Self::MATE_BOUND
+        } else if score < -Self::MATE_BOUND {
+            -Self::MATE_BOUND
+        } else {
+            score
+        }
+    }
+}
+
+const _: () = {
+    assert!(Score::MATE < Score::INFINITY);
+    // Window arithmetic such as `(alpha + beta) / 2` or `-alpha - 1` can't overflow
+    assert!(Score::INFINITY.checked_mul(2).is_some());
+    // Mates found within the maximum ply are still recognized as mates
+    assert!(Score::MATE - MAX_PLY as i32 > Score::MATE_BOUND);
+    assert!(Score::TB_WIN - MAX_PLY as i32 > Score::MAX_EVAL);
+    assert!(Score::TB_WIN < Score::MATE_BOUND);
+    // No margin pushes an evaluation into the mate range
+    assert!(Score::offset(Score::MAX_EVAL, i32::MAX) == Score::MATE_BOUND);
+    assert!(Score::offset(-Score::MAX_EVAL, i32::MIN) == -Score::MATE_BOUND);
+};
+
+#[cfg(test)]
+mod tests {
+    use super::Score;
+    use crate::types::MAX_PLY;
+
+    #[test]
+    fn test_margin_saturates() {
+        assert_eq!(Score::margin(i32::MAX, 2), i32::MAX);
+        assert_eq!(Score::margin(-1000, i32::MAX), i32::MIN);
+        assert_eq!(
+            Score::offset(Score::MATE_BOUND - 1, i32::MAX),
+            Score::MATE_BOUND
+        );
+        assert_eq!(
+            Score::offset(-Score::MATE_BOUND + 1, i32::MIN),
+            -Score::MATE_BOUND
+        );
+        assert_eq!(Score::offset(100, Score::margin(75, 3)), 325);
+    }
+
+    #[test]
+    fn test_result_classes() {
+        assert!(Score::is_mate(Score::MATE_BOUND + 1));
+        assert!(!Score::is_mate(Score::MATE_BOUND));
+        assert!(Score::is_mate(-Score::MATE_BOUND - 1));
+        assert!(!Score::is_mate(-Score::MATE_BOUND));
+        assert!(Score::is_mate(Score::MATE - MAX_PLY as i32));
+        assert!(Score::is_mate(Score::mated_in(MAX_PLY)));
+        assert!(!Score::is_mate(Score::tb_win_in(0)));
+
+        assert!(Score::is_win(Score::MAX_EVAL + 1));
+        assert!(!Score::is_win(Score::MAX_EVAL));
+        assert!(Score::is_win(Score::tb_win_in(MAX_PLY)));
+        assert!(Score::is_win(Score::MATE - 1));
+        assert!(!Score::is_win(-Score::MATE + 1));
+        assert!(Score::is_loss(-Score::MAX_EVAL - 1));
+        assert!(!Score::is_loss(-Score::MAX_EVAL));
+        assert!(Score::is_loss(Score::mated_in(0)));
+
+        for score in [Score::MAX_EVAL, -Score::MAX_EVAL, Score::DRAW] {
+            assert!(!Score::is_decisive(score));
+        }
+        for score in [
+            Score::MAX_EVAL + 1,
+            -Score::tb_win_in(3),
+            Score::mated_in(5),
+        ] {
+            assert!(Score::is_decisive(score));
+        }
+    }
+
+    #[test]
+    fn test_to_uci() {
+        assert_eq!(Score::to_uci(Score::MATE - 1), "mate 1");
+        assert_eq!(Score::to_uci(Score::MATE - 5), "mate 3");
+        assert_eq!(Score::to_uci(Score::mated_in(2)), "mate -1");
+        assert_eq!(Score::to_uci(Score::mated_in(4)), "mate -2");
+        assert_eq!(
+            Score::to_uci(Score::tb_win_in(3)),
+            format!("cp {}", Score::tb_win_in(3))
+        );
+        assert_eq!(Score::to_uci(-35), "cp -35");
+    }
+
+    #[test]
+    fn test_to_wdl() {
+        assert_eq!(Score::to_wdl(Score::MATE - 5, 40), (1000, 0, 0));
+        assert_eq!(Score::to_wdl(Score::mated_in(3), 40), (0, 0, 1000));
+        assert_eq!(Score::to_wdl(-Score::tb_win_in(10), 40), (0, 0, 1000));
+
+        let (win, draw, loss) = Score::to_wdl(0, 0);
+        assert_eq!(win, loss);
+        assert_eq!(win + draw + loss, 1000);
+
+        let (win, _, loss) = Score::to_wdl(150, 40);
+        assert!(win > loss);
+        assert_eq!(Score::to_wdl(-150, 40), (loss, 1000 - win - loss, win));
+        // The same advantage wins more often later in the game
+        assert!(Score::to_wdl(150, 120).0 > win);
+    }
 }